@@ -0,0 +1,590 @@
+//! iCalendar (.ics) import for focus schedules.
+//!
+//! Each VEVENT whose summary or categories match
+//! `GlobalSettings.calendar_import` becomes a `Schedule` with id
+//! `ical:<UID>`, so importing the same calendar again updates entries
+//! instead of duplicating them. The CLI tags each schedule with the file it
+//! came from and drops that file's old schedules on re-import, so deleted
+//! events are removed too.
+//!
+//! Supported recurrence: none (one-off), FREQ=WEEKLY with BYDAY and
+//! INTERVAL, and FREQ=DAILY with BYDAY, bounded by UNTIL or COUNT, minus
+//! EXDATE dates. A RECURRENCE-ID override removes the original occurrence
+//! and is imported as a one-off of its own. Other rules, all-day events and
+//! events of 24 hours or more are skipped with a reason.
+//!
+//! Event times are converted to the system timezone at the first
+//! occurrence; later occurrences keep that wall-clock time.
+
+use crate::config::{CalendarImport, Mode};
+use crate::schedule::{ClockTime, Day, Schedule};
+use crate::AppError;
+use chrono::{
+    DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Timelike, Utc,
+};
+use ical::parser::ical::component::IcalEvent;
+use ical::property::Property;
+use ical::IcalParser;
+use regex::Regex;
+use std::io::BufRead;
+
+/// Prefix of schedule ids created by an import.
+const ID_PREFIX: &str = "ical:";
+
+/// How far ahead a COUNT-bounded rule is expanded before giving up.
+const MAX_COUNT_DAYS: i64 = 366 * 10;
+
+/// Outcome of parsing a calendar.
+#[derive(Debug, Default)]
+pub struct Import {
+    pub schedules: Vec<Schedule>,
+    /// Ids of cancelled events, to drop from the config.
+    pub cancelled: Vec<String>,
+    /// Matching events that can't be represented, with the reason.
+    pub skipped: Vec<String>,
+}
+
+/// Parse every VEVENT in `reader`, converting times to the system timezone.
+pub fn parse(reader: impl BufRead, rules: &CalendarImport) -> Result<Import, AppError> {
+    parse_in(reader, rules, &chrono::Local)
+}
+
+/// Check that a pattern compiles, for settings validation.
+pub fn compile_pattern(pattern: &str) -> Result<Option<Regex>, AppError> {
+    if pattern.is_empty() {
+        return Ok(None);
+    }
+    Regex::new(pattern)
+        .map(Some)
+        .map_err(|e| AppError::Config(format!("Invalid calendar pattern '{pattern}': {e}")))
+}
+
+fn parse_in<Tz: TimeZone>(
+    reader: impl BufRead,
+    rules: &CalendarImport,
+    local: &Tz,
+) -> Result<Import, AppError> {
+    let strict = compile_pattern(&rules.strict_pattern)?;
+    let precision = compile_pattern(&rules.precision_pattern)?;
+
+    let mut events = Vec::new();
+    for calendar in IcalParser::new(reader) {
+        let calendar = calendar.map_err(|e| AppError::Calendar(e.to_string()))?;
+        events.extend(calendar.events);
+    }
+
+    let mut import = Import::default();
+    // (uid, local date) of occurrences replaced by an override.
+    let mut moved: Vec<(String, NaiveDate)> = Vec::new();
+
+    for event in &events {
+        let Some(uid) = value(event, "UID") else {
+            continue;
+        };
+        let mut id = format!("{ID_PREFIX}{uid}");
+
+        if let Some(original) = property(event, "RECURRENCE-ID") {
+            match parse_time(original, local).map(|t| t.local_date(local)) {
+                Ok(date) => {
+                    id = format!("{id}@{date}");
+                    moved.push((uid.to_string(), date));
+                }
+                Err(e) => {
+                    import.skipped.push(format!("{uid}: RECURRENCE-ID: {e}"));
+                    continue;
+                }
+            }
+        }
+
+        if value(event, "STATUS").is_some_and(|s| s.eq_ignore_ascii_case("CANCELLED")) {
+            import.cancelled.push(id);
+            continue;
+        }
+
+        let Some(mode) = event_mode(event, strict.as_ref(), precision.as_ref()) else {
+            continue;
+        };
+
+        match event_schedule(event, id, mode, local) {
+            Ok(schedule) => import.schedules.push(schedule),
+            Err(e) => {
+                let summary = value(event, "SUMMARY").unwrap_or(uid);
+                import.skipped.push(format!("{summary}: {e}"));
+            }
+        }
+    }
+
+    for (uid, date) in moved {
+        let master = format!("{ID_PREFIX}{uid}");
+        if let Some(s) = import.schedules.iter_mut().find(|s| s.id == master) {
+            s.except.push(date);
+        }
+    }
+
+    Ok(import)
+}
+
+fn property<'a>(event: &'a IcalEvent, name: &str) -> Option<&'a Property> {
+    event
+        .properties
+        .iter()
+        .find(|p| p.name.eq_ignore_ascii_case(name))
+}
+
+fn value<'a>(event: &'a IcalEvent, name: &str) -> Option<&'a str> {
+    property(event, name).and_then(|p| p.value.as_deref())
+}
+
+fn param<'a>(p: &'a Property, name: &str) -> Option<&'a str> {
+    p.params
+        .as_ref()?
+        .iter()
+        .find(|(key, _)| key == name)
+        .and_then(|(_, values)| values.first())
+        .map(String::as_str)
+}
+
+/// Strict if the summary or any category matches the strict pattern,
+/// precision if one matches the precision pattern, otherwise not imported.
+fn event_mode(
+    event: &IcalEvent,
+    strict: Option<&Regex>,
+    precision: Option<&Regex>,
+) -> Option<Mode> {
+    let mut texts: Vec<&str> = value(event, "SUMMARY").into_iter().collect();
+    for p in &event.properties {
+        if p.name.eq_ignore_ascii_case("CATEGORIES") {
+            texts.extend(p.value.iter().flat_map(|v| v.split(',')).map(str::trim));
+        }
+    }
+
+    let matches = |re: Option<&Regex>| re.is_some_and(|re| texts.iter().any(|t| re.is_match(t)));
+    if matches(strict) {
+        Some(Mode::Strict)
+    } else if matches(precision) {
+        Some(Mode::Precision)
+    } else {
+        None
+    }
+}
+
+fn event_schedule<Tz: TimeZone>(
+    event: &IcalEvent,
+    id: String,
+    mode: Mode,
+    local: &Tz,
+) -> Result<Schedule, String> {
+    let instant = |name: &str| -> Result<Option<DateTime<Utc>>, String> {
+        match property(event, name)
+            .map(|p| parse_time(p, local))
+            .transpose()?
+        {
+            Some(IcalTime::Instant(t)) => Ok(Some(t)),
+            Some(IcalTime::Date(_)) => Err("all-day events are not supported".into()),
+            None => Ok(None),
+        }
+    };
+
+    let start = instant("DTSTART")?.ok_or("missing DTSTART")?;
+    let end = match (instant("DTEND")?, value(event, "DURATION")) {
+        (Some(end), _) => end,
+        (None, Some(d)) => start + parse_duration(d).ok_or(format!("invalid DURATION {d}"))?,
+        (None, None) => return Err("missing DTEND or DURATION".into()),
+    };
+    if end <= start || end - start >= TimeDelta::days(1) {
+        return Err("events must be shorter than 24 hours".into());
+    }
+
+    let local_start = start.with_timezone(local).naive_local();
+    let local_end = end.with_timezone(local).naive_local();
+    let start_time = clock(local_start);
+    let end_time = clock(local_end);
+    if start_time == end_time {
+        return Err("events must be at least a minute long".into());
+    }
+
+    let first = local_start.date();
+    let mut schedule = Schedule {
+        id,
+        days: vec![first.weekday().into()],
+        start_time,
+        end_time,
+        mode,
+        locked: false,
+        enabled: true,
+        starts_on: Some(first),
+        ends_on: Some(first),
+        interval_weeks: 1,
+        except: Vec::new(),
+        source: None,
+        last_run: None,
+    };
+
+    if let Some(rule) = value(event, "RRULE") {
+        apply_rrule(&mut schedule, rule, local, local_start.time())?;
+    }
+
+    for p in &event.properties {
+        if !p.name.eq_ignore_ascii_case("EXDATE") {
+            continue;
+        }
+        for v in p.value.iter().flat_map(|v| v.split(',')) {
+            let single = Property {
+                name: p.name.clone(),
+                params: p.params.clone(),
+                value: Some(v.trim().to_string()),
+            };
+            let date = parse_time(&single, local)?.local_date(local);
+            schedule.except.push(date);
+        }
+    }
+
+    Ok(schedule)
+}
+
+fn clock(t: NaiveDateTime) -> ClockTime {
+    ClockTime::new(t.hour(), t.minute()).expect("hour and minute from chrono are in range")
+}
+
+// =========================================================================
+// RRULE
+// =========================================================================
+
+fn apply_rrule<Tz: TimeZone>(
+    schedule: &mut Schedule,
+    rule: &str,
+    local: &Tz,
+    start_time: NaiveTime,
+) -> Result<(), String> {
+    let mut freq = None;
+    let mut interval = 1;
+    let mut by_day = None;
+    let mut until = None;
+    let mut count = None;
+
+    for part in rule.split(';').filter(|p| !p.is_empty()) {
+        let (key, v) = part
+            .split_once('=')
+            .ok_or(format!("malformed RRULE part {part}"))?;
+        match key.to_ascii_uppercase().as_str() {
+            "FREQ" => freq = Some(v.to_ascii_uppercase()),
+            "INTERVAL" => interval = v.parse().map_err(|_| format!("invalid INTERVAL {v}"))?,
+            "BYDAY" => by_day = Some(v.split(',').map(parse_byday).collect::<Result<_, _>>()?),
+            "UNTIL" => until = Some(v),
+            "COUNT" => count = Some(v.parse::<u32>().map_err(|_| format!("invalid COUNT {v}"))?),
+            // Weeks are always counted from Monday, the RFC default.
+            "WKST" => {}
+            other => return Err(format!("unsupported RRULE part {other}")),
+        }
+    }
+
+    match freq.as_deref() {
+        Some("WEEKLY") if interval > 0 => {
+            schedule.interval_weeks = interval;
+            if let Some(days) = by_day {
+                schedule.days = days;
+            }
+        }
+        Some("DAILY") if interval == 1 => {
+            schedule.days = by_day.unwrap_or_else(|| Day::ALL.to_vec());
+        }
+        Some(f) => {
+            return Err(format!(
+                "unsupported recurrence FREQ={f};INTERVAL={interval}"
+            ))
+        }
+        None => return Err("RRULE without FREQ".into()),
+    }
+
+    // Unbounded while counting occurrences.
+    schedule.ends_on = None;
+    schedule.ends_on = match (until, count) {
+        (Some(until), _) => Some(until_date(until, local, start_time)?),
+        (None, Some(count)) => Some(nth_occurrence(schedule, count)?),
+        (None, None) => None,
+    };
+    Ok(())
+}
+
+fn parse_byday(v: &str) -> Result<Day, String> {
+    let day = match v.trim().to_ascii_uppercase().as_str() {
+        "MO" => Day::Mon,
+        "TU" => Day::Tue,
+        "WE" => Day::Wed,
+        "TH" => Day::Thu,
+        "FR" => Day::Fri,
+        "SA" => Day::Sat,
+        "SU" => Day::Sun,
+        _ => return Err(format!("unsupported BYDAY {v}")),
+    };
+    Ok(day)
+}
+
+/// Last local date an occurrence may start on. UNTIL is inclusive, so a
+/// bound earlier in the day than the event start excludes that day.
+fn until_date<Tz: TimeZone>(
+    v: &str,
+    local: &Tz,
+    start_time: NaiveTime,
+) -> Result<NaiveDate, String> {
+    let until = Property {
+        name: "UNTIL".into(),
+        params: None,
+        value: Some(v.to_string()),
+    };
+    match parse_time(&until, local)? {
+        IcalTime::Date(d) => Ok(d),
+        IcalTime::Instant(t) => {
+            let t = t.with_timezone(local).naive_local();
+            if t.time() < start_time {
+                t.date().pred_opt().ok_or("UNTIL out of range".into())
+            } else {
+                Ok(t.date())
+            }
+        }
+    }
+}
+
+/// Date of the `count`th occurrence. EXDATEs still count toward COUNT, so
+/// this runs before they are added.
+fn nth_occurrence(schedule: &Schedule, count: u32) -> Result<NaiveDate, String> {
+    let first = schedule.starts_on.ok_or("missing start date")?;
+    let mut seen = 0;
+    for offset in 0..MAX_COUNT_DAYS {
+        let date = first + TimeDelta::days(offset);
+        if schedule.opens_on(date) {
+            seen += 1;
+            if seen >= count {
+                return Ok(date);
+            }
+        }
+    }
+    Err(format!(
+        "COUNT={count} runs more than {MAX_COUNT_DAYS} days"
+    ))
+}
+
+// =========================================================================
+// Date-time values
+// =========================================================================
+
+enum IcalTime {
+    Date(NaiveDate),
+    Instant(DateTime<Utc>),
+}
+
+impl IcalTime {
+    fn local_date<Tz: TimeZone>(&self, local: &Tz) -> NaiveDate {
+        match self {
+            IcalTime::Date(d) => *d,
+            IcalTime::Instant(t) => t.with_timezone(local).date_naive(),
+        }
+    }
+}
+
+/// Parse a DATE or DATE-TIME property value. UTC (`Z`) and `TZID` times are
+/// absolute; floating times are taken as system-local. An unknown TZID is
+/// treated as floating.
+fn parse_time<Tz: TimeZone>(p: &Property, local: &Tz) -> Result<IcalTime, String> {
+    let v = p.value.as_deref().unwrap_or_default().trim();
+
+    if param(p, "VALUE") == Some("DATE") || v.len() == 8 {
+        return NaiveDate::parse_from_str(v, "%Y%m%d")
+            .map(IcalTime::Date)
+            .map_err(|_| format!("invalid date {v}"));
+    }
+
+    let (naive, utc) = match v.strip_suffix('Z') {
+        Some(v) => (v, true),
+        None => (v, false),
+    };
+    let naive = NaiveDateTime::parse_from_str(naive, "%Y%m%dT%H%M%S")
+        .map_err(|_| format!("invalid date-time {v}"))?;
+
+    let instant = if utc {
+        Some(Utc.from_utc_datetime(&naive))
+    } else if let Some(tz) =
+        param(p, "TZID").and_then(|t| t.trim_start_matches('/').parse::<chrono_tz::Tz>().ok())
+    {
+        tz.from_local_datetime(&naive)
+            .earliest()
+            .map(|t| t.with_timezone(&Utc))
+    } else {
+        local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|t| t.with_timezone(&Utc))
+    };
+    instant
+        .map(IcalTime::Instant)
+        .ok_or(format!("{v} does not exist in its timezone"))
+}
+
+/// Parse an RFC 5545 duration such as `PT1H30M` or `P1D`. Negative
+/// durations are rejected.
+fn parse_duration(v: &str) -> Option<TimeDelta> {
+    let v = v.trim().strip_prefix('+').unwrap_or(v.trim());
+    let rest = v.strip_prefix('P')?;
+
+    let mut total = TimeDelta::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+    for c in rest.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => in_time = true,
+            _ => {
+                let n: i64 = number.parse().ok()?;
+                number.clear();
+                total += match (c, in_time) {
+                    ('W', false) => TimeDelta::weeks(n),
+                    ('D', false) => TimeDelta::days(n),
+                    ('H', true) => TimeDelta::hours(n),
+                    ('M', true) => TimeDelta::minutes(n),
+                    ('S', true) => TimeDelta::seconds(n),
+                    _ => return None,
+                };
+            }
+        }
+    }
+    number.is_empty().then_some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::America::New_York;
+    use chrono_tz::Europe::Berlin;
+
+    const ICS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:weekly-1\r
+SUMMARY:Deep work\r
+DTSTART;TZID=America/New_York:20260105T090000\r
+DTEND;TZID=America/New_York:20260105T113000\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,WE;COUNT=4\r
+EXDATE;TZID=America/New_York:20260107T090000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:weekly-1\r
+RECURRENCE-ID;TZID=America/New_York:20260112T090000\r
+SUMMARY:Deep work (moved)\r
+DTSTART;TZID=America/New_York:20260112T140000\r
+DURATION:PT1H\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:focus-2\r
+SUMMARY:Planning\r
+CATEGORIES:Admin,Focus\r
+DTSTART:20260106T200000Z\r
+DTEND:20260106T210000Z\r
+RRULE:FREQ=DAILY;UNTIL=20260109T200000Z\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:lunch\r
+SUMMARY:Lunch\r
+DTSTART:20260106T120000\r
+DTEND:20260106T130000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:allday\r
+SUMMARY:Focus day\r
+DTSTART;VALUE=DATE:20260108\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:monthly\r
+SUMMARY:Focus review\r
+DTSTART:20260110T100000\r
+DTEND:20260110T110000\r
+RRULE:FREQ=MONTHLY\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:gone\r
+SUMMARY:Deep work\r
+STATUS:CANCELLED\r
+DTSTART:20260110T100000\r
+DTEND:20260110T110000\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    fn date(m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, m, d).unwrap()
+    }
+
+    fn import_in<Tz: TimeZone>(tz: &Tz) -> Import {
+        parse_in(ICS.as_bytes(), &CalendarImport::default(), tz).unwrap()
+    }
+
+    #[test]
+    fn weekly_rule_with_count_exdate_and_override() {
+        let import = import_in(&New_York);
+        let s = import
+            .schedules
+            .iter()
+            .find(|s| s.id == "ical:weekly-1")
+            .unwrap();
+
+        assert_eq!(s.mode, Mode::Strict);
+        assert_eq!(s.days, [Day::Mon, Day::Wed]);
+        assert_eq!(s.start_time.to_string(), "09:00");
+        assert_eq!(s.end_time.to_string(), "11:30");
+        assert_eq!(s.starts_on, Some(date(1, 5)));
+        // COUNT=4: Jan 5, 7, 12, 14 (EXDATE still counts).
+        assert_eq!(s.ends_on, Some(date(1, 14)));
+        assert_eq!(s.except, [date(1, 7), date(1, 12)]);
+
+        let moved = import
+            .schedules
+            .iter()
+            .find(|s| s.id == "ical:weekly-1@2026-01-12")
+            .unwrap();
+        assert_eq!(moved.start_time.to_string(), "14:00");
+        assert_eq!(moved.end_time.to_string(), "15:00");
+        assert_eq!(
+            (moved.starts_on, moved.ends_on),
+            (Some(date(1, 12)), Some(date(1, 12)))
+        );
+    }
+
+    #[test]
+    fn utc_times_convert_to_local_and_categories_match() {
+        let import = import_in(&Berlin);
+        let s = import
+            .schedules
+            .iter()
+            .find(|s| s.id == "ical:focus-2")
+            .unwrap();
+
+        assert_eq!(s.mode, Mode::Precision);
+        assert_eq!(s.days.len(), 7);
+        assert_eq!(s.start_time.to_string(), "21:00");
+        assert_eq!(s.end_time.to_string(), "22:00");
+        assert_eq!(s.ends_on, Some(date(1, 9)));
+    }
+
+    #[test]
+    fn unmatched_unsupported_and_cancelled_events() {
+        let import = import_in(&New_York);
+        let ids: Vec<&str> = import.schedules.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            ["ical:weekly-1", "ical:weekly-1@2026-01-12", "ical:focus-2"],
+            "{:?}",
+            import.skipped
+        );
+        assert_eq!(import.cancelled, ["ical:gone"]);
+        assert_eq!(import.skipped.len(), 2, "{:?}", import.skipped);
+        assert!(import.skipped[0].contains("all-day"));
+        assert!(import.skipped[1].contains("FREQ=MONTHLY"));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("PT1H30M"), Some(TimeDelta::minutes(90)));
+        assert_eq!(parse_duration("P1DT2H"), Some(TimeDelta::hours(26)));
+        assert_eq!(parse_duration("-PT1H"), None);
+        assert_eq!(parse_duration("PT1H5"), None);
+    }
+}
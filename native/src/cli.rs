@@ -0,0 +1,1284 @@
+//! Operator command line.
+//!
+//! Apart from `setup` and `restore`, every subcommand is translated into the
+//! same `protocol::Request` the extension sends and run through
+//! `handle_message`, so scripts on a headless box and the browser drive
+//! sessions through exactly one code path.
+
+use crate::calendar;
+use crate::config::{self, EarlyEnd, GlobalSettings, Mode};
+use crate::history::{self, SessionRecord, Stats};
+use crate::protocol::{
+    CalendarImportPayload, CyclePayload, ErrorCode, ErrorReply, PausePolicyPayload, Reply, Request,
+    Response, SchedulePayload, SessionView, SettingsPayload, SettingsView, StateReply,
+    TamperPolicyPayload,
+};
+use crate::schedule::{ClockTime, Day};
+use crate::{handle_message, AppError, SecretInput};
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, BufReader, IsTerminal, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "focus-blocker-native",
+    version,
+    about = "Native enforcement agent for Focus Blocker",
+    arg_required_else_help = true
+)]
+pub struct Cli {
+    /// Print a single JSON document instead of text: the GET_STATE response,
+    /// with a report that isn't part of the state (stats, history, recovery
+    /// codes) under one more key
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// Read passwords and PINs from stdin, one per line, instead of
+    /// prompting on the terminal
+    #[arg(long, global = true)]
+    pub password_stdin: bool,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Interactive first-time admin password setup
+    Setup,
+    /// Re-measure password hashing cost on this machine
+    ///
+    /// Run it after moving to faster hardware, or on installs set up before
+    /// hashing was calibrated. The admin password is rehashed right away;
+    /// session PINs when they are next used.
+    Calibrate,
+    /// Re-apply persisted blocks and monitor session expiry
+    Restore,
+    /// Show the current session, blocks and settings
+    Status {
+        /// Stop reporting a config that was restored from a backup
+        #[arg(long)]
+        dismiss_recovery: bool,
+    },
+    /// Start a focus session
+    Start {
+        /// precision or strict [default: precision]
+        #[arg(long)]
+        mode: Option<Mode>,
+        /// Session length [default: 30]
+        #[arg(long)]
+        minutes: Option<u32>,
+        /// Require the PIN to end the session early
+        #[arg(long)]
+        locked: bool,
+        /// Work phase length, alternating with breaks
+        #[arg(long, requires = "break_minutes")]
+        work: Option<u32>,
+        /// Break length between work phases
+        #[arg(long = "break", requires = "work")]
+        break_minutes: Option<u32>,
+        /// Long break length
+        #[arg(long, requires = "long_break_every")]
+        long_break: Option<u32>,
+        /// Take a long break after every N work phases
+        #[arg(long, requires_all = ["long_break", "work"])]
+        long_break_every: Option<u32>,
+    },
+    /// End the current session
+    ///
+    /// Asks for the session PIN if the session is locked (the admin password
+    /// while no PIN is set), then for the admin password if the early-end
+    /// policy requires it. With --password-stdin, one line each, in that
+    /// order.
+    Stop {
+        /// Confirmation phrase, if the early-end policy asks for one
+        #[arg(long)]
+        phrase: Option<String>,
+    },
+    /// Push the end of the current session back
+    Extend {
+        #[arg(long)]
+        minutes: u32,
+    },
+    /// Pause the current session (lifts strict-mode blocking)
+    ///
+    /// Asks for the session PIN if the session is locked.
+    Pause,
+    /// Resume a paused session
+    Resume,
+    /// Switch the running session to another mode
+    ///
+    /// Strict to precision lifts the hosts block, so it asks for the same
+    /// PIN and admin password as `stop`.
+    Switch {
+        mode: Mode,
+        /// Confirmation phrase, if the early-end policy asks for one
+        #[arg(long)]
+        phrase: Option<String>,
+    },
+    /// Add a domain to the blocklist
+    Block { domain: String },
+    /// Remove a domain from the blocklist
+    Unblock { domain: String },
+    /// List blocked domains and the hostnames they expand to
+    List,
+    /// Show YouTube channel rules and custom domain families
+    Rules,
+    /// Focus time per day and week, and streaks
+    Stats {
+        /// Days to list [default: 7, max: 366]
+        #[arg(long)]
+        days: Option<u32>,
+        /// Weeks to list [default: 4, max: 53]
+        #[arg(long)]
+        weeks: Option<u32>,
+    },
+    /// Export ended sessions
+    History {
+        #[command(subcommand)]
+        action: HistoryAction,
+    },
+    /// Read or change global settings
+    Settings {
+        #[command(subcommand)]
+        action: SettingsAction,
+    },
+    /// Manage recurring sessions started by `restore`
+    Schedules {
+        #[command(subcommand)]
+        action: SchedulesAction,
+    },
+    /// Manage the admin password
+    Password {
+        #[command(subcommand)]
+        action: PasswordAction,
+    },
+    /// Manage session PINs, which can only end or pause locked sessions
+    Pin {
+        #[command(subcommand)]
+        action: PinAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PasswordAction {
+    /// Replace the admin password (asks for the current one)
+    Change,
+    /// Set a new admin password with a recovery code from setup
+    ///
+    /// Asks for the code, then the new password. With --password-stdin, one
+    /// line each, in that order.
+    Reset,
+    /// Replace all recovery codes with a fresh set
+    RecoveryCodes,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PinAction {
+    /// List PIN labels
+    List,
+    /// Add a PIN, or replace the one with the same label
+    Set { label: String },
+    /// Remove a PIN
+    Remove { label: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SchedulesAction {
+    /// Print all schedules
+    List,
+    /// Add a schedule, or replace the one with the same id
+    Add {
+        /// Defaults to a generated id
+        #[arg(long)]
+        id: Option<String>,
+        /// Comma-separated, e.g. mon,tue,wed
+        #[arg(long, value_delimiter = ',', required = true)]
+        days: Vec<Day>,
+        /// Local time, HH:MM
+        #[arg(long)]
+        start: ClockTime,
+        /// Local time, HH:MM; at or before `start` runs past midnight
+        #[arg(long)]
+        end: ClockTime,
+        #[arg(long, default_value = "strict")]
+        mode: Mode,
+        /// Require the PIN to end scheduled sessions early
+        #[arg(long)]
+        locked: bool,
+    },
+    /// Remove a schedule
+    Remove { id: String },
+    /// Import matching events from an iCalendar (.ics) file
+    Import { file: PathBuf },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HistoryAction {
+    /// Print sessions to stdout
+    Export {
+        /// Ignored with --json, which puts the sessions in its document
+        #[arg(long, default_value = "csv")]
+        format: ExportFormat,
+        /// First local date to include, YYYY-MM-DD
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        /// Last local date to include, YYYY-MM-DD
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        /// One row with the website's session_stats columns instead
+        #[arg(long)]
+        session_stats: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SettingsAction {
+    /// Print all settings
+    Get,
+    /// Change one setting
+    Set { key: SettingKey, value: String },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum SettingKey {
+    DefaultMode,
+    BlockAllChannels,
+    SessionDuration,
+    /// Comma-separated list of IP addresses
+    SinkAddresses,
+    TamperExtendMinutes,
+    /// A number, or "none" to disable
+    TamperLockAfter,
+    /// A number, or "none" for no limit
+    MaxPauses,
+    /// A number, or "none" for no limit
+    MaxPauseMinutes,
+    /// Regex for events imported as strict sessions ("" to disable)
+    CalendarStrictPattern,
+    /// Regex for events imported as precision sessions ("" to disable)
+    CalendarPrecisionPattern,
+    /// none, password, wait:MINUTES or phrase:TEXT
+    EarlyEnd,
+    /// Sessions per day, or "none" for no limit
+    DailySessionLimit,
+}
+
+/// True when the process was started by a browser rather than a person.
+///
+/// Chrome and Edge pass the caller origin (`chrome-extension://…/`) as the
+/// first argument; Firefox passes the path to the host manifest followed by
+/// the extension id. A bare invocation counts only if stdin is a pipe, so
+/// running the binary on a terminal prints help instead of hanging.
+pub fn is_native_messaging_launch(args: &[String]) -> bool {
+    match args.get(1..).unwrap_or_default() {
+        [] => !io::stdin().is_terminal(),
+        rest => is_browser_launch(rest),
+    }
+}
+
+/// The argument shapes browsers launch the host with (program name excluded).
+fn is_browser_launch(args: &[String]) -> bool {
+    match args {
+        // Windows Chrome appends `--parent-window=<hwnd>`.
+        [origin, ..] if origin.starts_with("chrome-extension://") => true,
+        [origin] if origin.starts_with("moz-extension://") => true,
+        [manifest, extension_id] => {
+            manifest.ends_with(".json")
+                && Path::new(manifest).is_file()
+                && !extension_id.is_empty()
+                && !extension_id.starts_with('-')
+        }
+        _ => false,
+    }
+}
+
+/// Parse the command line. With `--json`, usage errors are reported as a
+/// JSON error document too (help and version output stay plain text).
+pub fn parse(args: Vec<String>) -> Cli {
+    let json = args.iter().any(|a| a == "--json");
+    match Cli::try_parse_from(args) {
+        Ok(cli) => cli,
+        Err(e) if json && e.use_stderr() => {
+            let text = e.to_string();
+            let message = text.lines().next().unwrap_or_default();
+            let message = message.strip_prefix("error: ").unwrap_or(message);
+            print_json(&Response::error(ErrorCode::InvalidArguments, message));
+            std::process::exit(2);
+        }
+        Err(e) => e.exit(),
+    }
+}
+
+/// Report a failed command on stderr, or as a JSON error document on stdout.
+pub fn report_error(e: &AppError, json: bool) {
+    match e {
+        AppError::Rejected(reply) if json => print_json(&Response::Error(reply.clone())),
+        _ if json => print_json(&Response::error(e.code(), e.to_string())),
+        AppError::Rejected(reply) => eprintln!("Error: {}", reply.message),
+        _ => eprintln!("[FocusBlocker] Fatal: {e}"),
+    }
+}
+
+pub fn run(cli: Cli) -> Result<(), AppError> {
+    let input = match cli.password_stdin {
+        true => SecretInput::Stdin,
+        false => SecretInput::Terminal,
+    };
+    let request = match cli.command {
+        Command::Setup => {
+            let Some(codes) = crate::run_setup(input)? else {
+                return print_state(cli.json);
+            };
+            // Kept off stdout unless asked for, so `setup --json` stays a
+            // single JSON document.
+            match cli.json {
+                true => print_document(&state_document(Some((
+                    "recoveryCodes",
+                    serde_json::to_value(codes)?,
+                )))?),
+                false => {
+                    eprintln!("Recovery codes (each works once, they will not be shown again):");
+                    for code in &codes {
+                        eprintln!("  {code}");
+                    }
+                }
+            }
+            return Ok(());
+        }
+        Command::Calibrate => {
+            crate::run_calibrate(input)?;
+            return print_state(cli.json);
+        }
+        Command::Restore => {
+            crate::run_restore()?;
+            return print_state(cli.json);
+        }
+
+        Command::Status {
+            dismiss_recovery: true,
+        } => Request::DismissRecovery {},
+        Command::Status { .. } | Command::List | Command::Rules => Request::GetState {},
+        Command::Stats { days, weeks } => Request::GetStats { days, weeks },
+        Command::History {
+            action: HistoryAction::Export { from, to, .. },
+        } => Request::GetHistory { from, to },
+        Command::Settings {
+            action: SettingsAction::Get,
+        }
+        | Command::Schedules {
+            action: SchedulesAction::List,
+        }
+        | Command::Pin {
+            action: PinAction::List,
+        } => Request::GetState {},
+
+        Command::Start {
+            mode,
+            minutes,
+            locked,
+            work,
+            break_minutes,
+            long_break,
+            long_break_every,
+        } => Request::StartSession {
+            duration_minutes: minutes,
+            scheduled_id: None,
+            locked,
+            mode,
+            cycle: work.zip(break_minutes).map(|(work, brk)| CyclePayload {
+                work_minutes: work,
+                break_minutes: brk,
+                long_break_minutes: long_break.unwrap_or(0),
+                long_break_every: long_break_every.unwrap_or(0),
+            }),
+        },
+        Command::Stop { ref phrase } => Request::EndSession {
+            natural: false,
+            parent_pin: session_pin(input)?,
+            confirmation: phrase.clone(),
+            password: early_end_password(input)?,
+        },
+        Command::Extend { minutes } => Request::ExtendSession { minutes },
+        Command::Pause => Request::PauseSession {
+            parent_pin: session_pin(input)?,
+        },
+        Command::Resume => Request::ResumeSession {},
+        Command::Switch { mode, ref phrase } if lifts_block(mode)? => Request::SwitchMode {
+            mode,
+            parent_pin: session_pin(input)?,
+            confirmation: phrase.clone(),
+            password: early_end_password(input)?,
+        },
+        Command::Switch { mode, ref phrase } => Request::SwitchMode {
+            mode,
+            parent_pin: None,
+            confirmation: phrase.clone(),
+            password: None,
+        },
+        Command::Block { ref domain } => Request::BlockDomain {
+            domain: domain.clone(),
+        },
+        Command::Unblock { ref domain } => Request::UnblockDomain {
+            domain: domain.clone(),
+        },
+        Command::Settings {
+            action: SettingsAction::Set { key, ref value },
+        } => Request::SyncSettings {
+            settings: settings_payload(key, value)?,
+            password: admin_password(input, "Admin password: ")?,
+        },
+        Command::Schedules { ref action } => Request::SyncSchedules {
+            schedules: schedules_payload(action)?,
+        },
+
+        Command::Password {
+            action: PasswordAction::Change,
+        } => Request::ChangePassword {
+            current_password: admin_password(input, "Current password: ")?,
+            new_password: new_secret(input, "New password: ", "Confirm password: ")?,
+        },
+        Command::Password {
+            action: PasswordAction::Reset,
+        } => Request::ResetPassword {
+            recovery_code: input.read("Recovery code: ")?,
+            new_password: new_secret(input, "New password: ", "Confirm password: ")?,
+        },
+        Command::Password {
+            action: PasswordAction::RecoveryCodes,
+        } => Request::RegenerateRecoveryCodes {
+            password: admin_password(input, "Admin password: ")?,
+        },
+        Command::Pin {
+            action: PinAction::Set { ref label },
+        } => Request::SetPin {
+            password: admin_password(input, "Admin password: ")?,
+            label: label.clone(),
+            pin: new_secret(input, "New PIN: ", "Confirm PIN: ")?,
+        },
+        Command::Pin {
+            action: PinAction::Remove { ref label },
+        } => Request::RemovePin {
+            password: admin_password(input, "Admin password: ")?,
+            label: label.clone(),
+        },
+    };
+
+    let reply = match handle_message(request)?.0 {
+        Response::Ok(reply) => reply,
+        Response::Error(e) => return Err(AppError::Rejected(e)),
+    };
+
+    if cli.json {
+        print_document(&json_document(&cli.command, reply)?);
+        return Ok(());
+    }
+    if let (Command::History { action }, Reply::History { sessions }) = (&cli.command, &reply) {
+        return export(action, sessions);
+    }
+    print_reply(&cli.command, reply);
+    Ok(())
+}
+
+/// The `--json` document for a command's reply. Scripts get the same shape
+/// whatever the command: the state it left, plus under one more key any
+/// report that isn't part of the state. A history export goes there too,
+/// whatever `--format` says.
+fn json_document(command: &Command, reply: Reply) -> Result<serde_json::Value, AppError> {
+    let session_stats = matches!(
+        command,
+        Command::History {
+            action: HistoryAction::Export {
+                session_stats: true,
+                ..
+            }
+        }
+    );
+    let extra = match reply {
+        Reply::Stats { stats } => Some(("stats", serde_json::to_value(stats)?)),
+        Reply::RecoveryCodes { recovery_codes } => {
+            Some(("recoveryCodes", serde_json::to_value(recovery_codes)?))
+        }
+        Reply::History { sessions } if session_stats => Some((
+            "sessionStats",
+            serde_json::to_value(history::session_stats(&sessions))?,
+        )),
+        Reply::History { sessions } => Some(("history", serde_json::to_value(sessions)?)),
+        _ => None,
+    };
+    state_document(extra)
+}
+
+/// The GET_STATE response, with `extra` added as one more key.
+fn state_document(extra: Option<(&str, serde_json::Value)>) -> Result<serde_json::Value, AppError> {
+    let mut doc = serde_json::to_value(handle_message(Request::GetState {})?.0)?;
+    if let (Some((key, value)), Some(fields)) = (extra, doc.as_object_mut()) {
+        fields.insert(key.into(), value);
+    }
+    Ok(doc)
+}
+
+/// With `--json`, print the current GET_STATE document.
+fn print_state(json: bool) -> Result<(), AppError> {
+    if json {
+        print_document(&state_document(None)?);
+    }
+    Ok(())
+}
+
+fn print_document(doc: &serde_json::Value) {
+    println!("{doc}");
+}
+
+fn print_json(response: &Response) {
+    match serde_json::to_string(response) {
+        Ok(doc) => println!("{doc}"),
+        Err(e) => eprintln!("[FocusBlocker] Cannot serialize response: {e}"),
+    }
+}
+
+// =========================================================================
+// Credentials
+// =========================================================================
+
+/// Ask for the admin password, if one is set.
+fn admin_password(input: SecretInput, label: &str) -> Result<Option<String>, AppError> {
+    if config::load()?.password_hash.is_none() {
+        return Ok(None);
+    }
+    input.read(label).map(Some)
+}
+
+/// True if switching the running session to `mode` lifts the hosts block.
+fn lifts_block(mode: Mode) -> Result<bool, AppError> {
+    Ok(mode == Mode::Precision && config::load()?.session_mode() == Mode::Strict)
+}
+
+/// Ask for a session PIN, if the running session is locked.
+fn session_pin(input: SecretInput) -> Result<Option<String>, AppError> {
+    let locked = config::load()?
+        .session
+        .is_some_and(|s| s.mode.is_active() && s.locked);
+    if !locked {
+        return Ok(None);
+    }
+    input.read("Session PIN: ").map(Some)
+}
+
+/// Ask for the admin password, if the early-end policy requires it.
+fn early_end_password(input: SecretInput) -> Result<Option<String>, AppError> {
+    let required = config::load()?
+        .global_settings
+        .is_some_and(|gs| gs.early_end == EarlyEnd::Password);
+    if !required {
+        return Ok(None);
+    }
+    admin_password(input, "Admin password: ")
+}
+
+/// Ask for a new password or PIN, twice on a terminal.
+fn new_secret(input: SecretInput, label: &str, confirm: &str) -> Result<String, AppError> {
+    let secret = input.read(label)?;
+    input.confirm(&secret, confirm)?;
+    Ok(secret)
+}
+
+// =========================================================================
+// settings set
+// =========================================================================
+
+/// Build a one-field settings update from `key=value`.
+fn settings_payload(key: SettingKey, value: &str) -> Result<SettingsPayload, AppError> {
+    let invalid = |detail: String| {
+        AppError::Rejected(ErrorReply::new(
+            ErrorCode::InvalidSettings,
+            format!("Invalid value for {}: {detail}", key_name(key)),
+        ))
+    };
+    let number = |v: &str| v.parse::<u32>().map_err(|e| invalid(e.to_string()));
+
+    let mut payload = SettingsPayload::default();
+    match key {
+        SettingKey::DefaultMode => {
+            payload.default_mode = Some(value.parse().map_err(invalid)?);
+        }
+        SettingKey::BlockAllChannels => {
+            payload.block_all_channels = Some(value.parse().map_err(|e| invalid(format!("{e}")))?);
+        }
+        SettingKey::SessionDuration => {
+            payload.session_duration_minutes = Some(number(value)?);
+        }
+        SettingKey::SinkAddresses => {
+            let sinks = value
+                .split(',')
+                .map(|s| s.trim().parse::<IpAddr>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| invalid(e.to_string()))?;
+            payload.sink_addresses = Some(sinks);
+        }
+        SettingKey::TamperExtendMinutes | SettingKey::TamperLockAfter => {
+            // The policy is replaced as a whole, so carry over the other half.
+            let current = current_settings()?.tamper_policy;
+            let mut policy = TamperPolicyPayload {
+                extend_minutes: current.extend_minutes,
+                lock_after: current.lock_after,
+            };
+            match key {
+                SettingKey::TamperExtendMinutes => policy.extend_minutes = number(value)?,
+                _ if value == "none" => policy.lock_after = None,
+                _ => policy.lock_after = Some(number(value)?),
+            }
+            payload.tamper_policy = Some(policy);
+        }
+        SettingKey::MaxPauses | SettingKey::MaxPauseMinutes => {
+            let current = current_settings()?.pause_policy;
+            let mut policy = PausePolicyPayload {
+                max_pauses: current.max_pauses,
+                max_pause_minutes: current.max_pause_minutes,
+            };
+            let limit = match value {
+                "none" => None,
+                v => Some(number(v)?),
+            };
+            match key {
+                SettingKey::MaxPauses => policy.max_pauses = limit,
+                _ => policy.max_pause_minutes = limit,
+            }
+            payload.pause_policy = Some(policy);
+        }
+        SettingKey::CalendarStrictPattern => {
+            payload.calendar_import = Some(CalendarImportPayload {
+                strict_pattern: Some(value.to_string()),
+                ..Default::default()
+            });
+        }
+        SettingKey::CalendarPrecisionPattern => {
+            payload.calendar_import = Some(CalendarImportPayload {
+                precision_pattern: Some(value.to_string()),
+                ..Default::default()
+            });
+        }
+        SettingKey::EarlyEnd => {
+            let policy = match value.split_once(':') {
+                _ if value == "none" => EarlyEnd::None,
+                _ if value == "password" => EarlyEnd::Password,
+                Some(("wait", minutes)) => EarlyEnd::Wait {
+                    minutes: number(minutes)?,
+                },
+                Some(("phrase", phrase)) => EarlyEnd::Phrase {
+                    phrase: phrase.to_string(),
+                },
+                _ => {
+                    return Err(invalid(
+                        "expected none, password, wait:MINUTES or phrase:TEXT".into(),
+                    ))
+                }
+            };
+            payload.early_end = Some(policy);
+        }
+        SettingKey::DailySessionLimit => {
+            payload.daily_session_limit = Some(match value {
+                "none" => None,
+                v => Some(number(v)?),
+            });
+        }
+    }
+    Ok(payload)
+}
+
+fn current_settings() -> Result<GlobalSettings, AppError> {
+    Ok(config::load()?.global_settings.unwrap_or_default())
+}
+
+fn key_name(key: SettingKey) -> String {
+    key.to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+// =========================================================================
+// schedules add / remove / import
+// =========================================================================
+
+/// The full schedule list after applying an add, remove or import.
+fn schedules_payload(action: &SchedulesAction) -> Result<Vec<SchedulePayload>, AppError> {
+    let mut schedules: Vec<SchedulePayload> = config::load()?
+        .schedules
+        .into_iter()
+        .map(SchedulePayload::from)
+        .collect();
+
+    match action {
+        SchedulesAction::List => {}
+        SchedulesAction::Add {
+            id,
+            days,
+            start,
+            end,
+            mode,
+            locked,
+        } => {
+            let id = id
+                .clone()
+                .unwrap_or_else(|| format!("sched-{:x}", config::now_ms()));
+            let schedule = SchedulePayload {
+                id: id.clone(),
+                days: days.clone(),
+                start_time: *start,
+                end_time: *end,
+                mode: *mode,
+                locked: *locked,
+                enabled: true,
+                starts_on: None,
+                ends_on: None,
+                interval_weeks: 1,
+                except: Vec::new(),
+                source: None,
+            };
+            upsert(&mut schedules, schedule);
+        }
+        SchedulesAction::Import { file } => {
+            let rules = current_settings()?.calendar_import;
+            let import = calendar::parse(BufReader::new(File::open(file)?), &rules)?;
+
+            for reason in &import.skipped {
+                eprintln!("Skipped {reason}");
+            }
+            eprintln!(
+                "Imported {} event(s) from {}.",
+                import.schedules.len(),
+                file.display()
+            );
+
+            let source = fs::canonicalize(file)?.display().to_string();
+            merge_import(&mut schedules, import, &source);
+        }
+        SchedulesAction::Remove { id } => {
+            let before = schedules.len();
+            schedules.retain(|s| s.id != *id);
+            if schedules.len() == before {
+                return Err(AppError::Rejected(ErrorReply::new(
+                    ErrorCode::InvalidSchedule,
+                    format!("No schedule with id '{id}'"),
+                )));
+            }
+        }
+    }
+    Ok(schedules)
+}
+
+/// Replace everything earlier imported from `source` with `import`, so
+/// events deleted from the calendar go away too.
+fn merge_import(schedules: &mut Vec<SchedulePayload>, import: calendar::Import, source: &str) {
+    schedules.retain(|s| s.source.as_deref() != Some(source) && !import.cancelled.contains(&s.id));
+    for schedule in import.schedules {
+        let mut schedule = SchedulePayload::from(schedule);
+        schedule.source = Some(source.to_string());
+        upsert(schedules, schedule);
+    }
+}
+
+/// Replace the schedule with the same id, or append.
+fn upsert(schedules: &mut Vec<SchedulePayload>, schedule: SchedulePayload) {
+    match schedules.iter_mut().find(|s| s.id == schedule.id) {
+        Some(existing) => *existing = schedule,
+        None => schedules.push(schedule),
+    }
+}
+
+// =========================================================================
+// history export
+// =========================================================================
+
+fn export(action: &HistoryAction, sessions: &[SessionRecord]) -> Result<(), AppError> {
+    let HistoryAction::Export {
+        format,
+        session_stats,
+        ..
+    } = action;
+    let out = io::stdout().lock();
+
+    match (format, session_stats) {
+        (ExportFormat::Csv, false) => history::write_csv(sessions, out),
+        (ExportFormat::Csv, true) => {
+            history::write_session_stats_csv(&history::session_stats(sessions), out)
+        }
+        (ExportFormat::Json, false) => write_json(sessions, out),
+        (ExportFormat::Json, true) => write_json(&history::session_stats(sessions), out),
+    }
+}
+
+fn write_json<T: Serialize + ?Sized, W: Write>(value: &T, mut out: W) -> Result<(), AppError> {
+    serde_json::to_writer_pretty(&mut out, value)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+// =========================================================================
+// Human-readable output
+// =========================================================================
+
+fn print_reply(command: &Command, reply: Reply) {
+    match (command, reply) {
+        (Command::Status { .. }, Reply::State(state)) => print_status(&state),
+        (Command::Status { .. }, _) => println!("Config recovery dismissed."),
+        (Command::List, Reply::State(state)) => print_list(&state),
+        (Command::Rules, Reply::State(state)) => print_rules(&state),
+        (Command::Settings { .. }, Reply::State(state)) => print_settings(&state),
+        (Command::Schedules { .. }, Reply::State(state)) => print_schedules(&state),
+        (Command::Pin { .. }, Reply::State(state)) => {
+            println!("{}", list_or_none(&state.session_pins));
+        }
+        (Command::Stats { .. }, Reply::Stats { stats }) => print_stats(&stats),
+
+        (Command::Start { .. }, Reply::Session { session, .. }) => {
+            println!(
+                "Started {} session, {}.",
+                session.mode,
+                remaining(session.end_time)
+            );
+        }
+        (Command::Stop { .. }, _) => println!("Session ended."),
+        (Command::Extend { .. }, Reply::Session { session, .. }) => {
+            match session.paused_remaining_ms {
+                Some(ms) => println!(
+                    "Session extended, paused with {} min left.",
+                    ms.div_ceil(60_000)
+                ),
+                None => println!("Session extended, {}.", remaining(session.end_time)),
+            }
+        }
+        (Command::Pause, _) => println!("Session paused."),
+        (Command::Resume, Reply::Session { session, .. }) => {
+            println!("Session resumed, {}.", remaining(session.end_time));
+        }
+        (Command::Switch { .. }, Reply::Mode { mode, .. }) => println!("Switched to {mode}."),
+        (Command::Block { domain }, _) => println!("Blocked {}.", domain.to_lowercase()),
+        (Command::Unblock { domain }, _) => println!("Unblocked {}.", domain.to_lowercase()),
+        (Command::Settings { .. }, _) => println!("Settings updated."),
+        (Command::Schedules { .. }, _) => println!("Schedules updated."),
+        (Command::Password { .. }, Reply::RecoveryCodes { recovery_codes }) => {
+            eprintln!("New recovery codes (each works once; the old ones no longer work):");
+            for code in recovery_codes {
+                println!("  {code}");
+            }
+        }
+        (Command::Password { .. }, _) => println!("Admin password changed."),
+        (Command::Pin { action }, _) => match action {
+            PinAction::Remove { label } => println!("Removed PIN '{label}'."),
+            _ => println!("Saved PIN."),
+        },
+        _ => {}
+    }
+}
+
+fn print_status(state: &StateReply) {
+    match state.session.as_ref().filter(|s| s.mode.is_active()) {
+        Some(session) => print_session(session),
+        None => println!("Session:  none"),
+    }
+    let daily = &state.daily_sessions;
+    match daily.limit {
+        Some(limit) => println!("Today:    {} of {limit} session(s)", daily.used),
+        None => println!("Today:    {} session(s)", daily.used),
+    }
+    println!("Blocked:  {}", list_or_none(&state.blocked_domains));
+    println!(
+        "Hosts:    {} hostname(s) sinkholed",
+        state.blocked_hosts.len()
+    );
+    if let Some(ref r) = state.config_recovered {
+        let at = chrono::DateTime::from_timestamp_millis(r.at as i64)
+            .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M"));
+        match at {
+            Some(at) => println!(
+                "Config:   restored from {} on {at} ({})",
+                r.backup, r.reason
+            ),
+            None => println!("Config:   restored from {} ({})", r.backup, r.reason),
+        }
+        println!("          `status --dismiss-recovery` hides this");
+    }
+}
+
+fn print_session(session: &SessionView) {
+    let lock = if session.locked { ", locked" } else { "" };
+    let time = match session.paused_remaining_ms {
+        Some(ms) => format!("paused, {} min left", ms.div_ceil(60_000)),
+        None => remaining(session.end_time),
+    };
+    println!("Session:  {}, {time}{lock}", session.mode);
+    if let Some(ref id) = session.scheduled_id {
+        println!("Schedule: {id}");
+    }
+    if let Some(ref cycle) = session.cycle {
+        println!(
+            "Phase:    {}, {} min left ({} work phase(s) done)",
+            cycle.phase,
+            cycle.phase_remaining_ms.div_ceil(60_000),
+            cycle.completed
+        );
+    }
+    if session.tamper_count > 0 {
+        println!("Tampers:  {}", session.tamper_count);
+    }
+}
+
+fn print_list(state: &StateReply) {
+    if state.blocked_domains.is_empty() {
+        println!("No blocked domains.");
+        return;
+    }
+    for domain in &state.blocked_domains {
+        println!("{domain}");
+    }
+    if !state.blocked_hosts.is_empty() {
+        println!();
+        println!("Sinkholed in the hosts file:");
+        for host in &state.blocked_hosts {
+            println!("  {host}");
+        }
+    }
+}
+
+fn print_rules(state: &StateReply) {
+    let (blocked, allowed) = state
+        .youtube_rules
+        .as_ref()
+        .map_or((&[][..], &[][..]), |r| {
+            (&r.blocked_channels[..], &r.allowed_channels[..])
+        });
+    println!("Blocked channels: {}", list_or_none(blocked));
+    println!("Allowed channels: {}", list_or_none(allowed));
+
+    if !state.domain_families.is_empty() {
+        println!("Domain families:");
+        for (domain, members) in &state.domain_families {
+            println!("  {domain}: {}", members.join(", "));
+        }
+    }
+}
+
+fn print_settings(state: &StateReply) {
+    let default;
+    let s = match state.settings {
+        Some(ref s) => s,
+        None => {
+            default = SettingsView::from(&GlobalSettings::default());
+            &default
+        }
+    };
+
+    let sinks: Vec<String> = s.sink_addresses.iter().map(IpAddr::to_string).collect();
+
+    println!("default-mode           {}", s.default_mode);
+    println!("block-all-channels     {}", s.block_all_channels);
+    println!("session-duration       {}", s.session_duration_minutes);
+    println!("sink-addresses         {}", sinks.join(","));
+    println!("tamper-extend-minutes  {}", s.tamper_policy.extend_minutes);
+    println!(
+        "tamper-lock-after      {}",
+        limit(s.tamper_policy.lock_after)
+    );
+    println!(
+        "max-pauses             {}",
+        limit(s.pause_policy.max_pauses)
+    );
+    println!(
+        "max-pause-minutes      {}",
+        limit(s.pause_policy.max_pause_minutes)
+    );
+    println!("early-end              {}", early_end(&s.early_end));
+    println!("daily-session-limit    {}", limit(s.daily_session_limit));
+    println!(
+        "calendar-strict-pattern     {}",
+        s.calendar_import.strict_pattern
+    );
+    println!(
+        "calendar-precision-pattern  {}",
+        s.calendar_import.precision_pattern
+    );
+}
+
+fn print_stats(stats: &Stats) {
+    println!(
+        "Streak:   {} day(s), longest {}",
+        stats.current_streak, stats.longest_streak
+    );
+    println!(
+        "Total:    {} session(s), {} min",
+        stats.total_sessions, stats.total_focus_minutes
+    );
+
+    for (heading, periods) in [("Day", &stats.days), ("Week of", &stats.weeks)] {
+        if periods.is_empty() {
+            continue;
+        }
+        println!();
+        println!("{heading:<12}{:>10}{:>10}", "Sessions", "Minutes");
+        for p in periods {
+            println!(
+                "{:<12}{:>10}{:>10}",
+                p.start.to_string(),
+                p.sessions,
+                p.focus_minutes
+            );
+        }
+    }
+}
+
+fn print_schedules(state: &StateReply) {
+    if state.schedules.is_empty() {
+        println!("No schedules.");
+        return;
+    }
+    for s in &state.schedules {
+        let days: Vec<&str> = s.days.iter().map(|d| d.as_str()).collect();
+        let mut flags = String::new();
+        if s.locked {
+            flags.push_str(" locked");
+        }
+        if s.interval_weeks > 1 {
+            flags.push_str(&format!(" every {} weeks", s.interval_weeks));
+        }
+        match (s.starts_on, s.ends_on) {
+            (Some(from), Some(until)) if from == until => flags.push_str(&format!(" on {from}")),
+            (Some(from), Some(until)) => flags.push_str(&format!(" {from}..{until}")),
+            (Some(from), None) => flags.push_str(&format!(" from {from}")),
+            (None, Some(until)) => flags.push_str(&format!(" until {until}")),
+            (None, None) => {}
+        }
+        if !s.except.is_empty() {
+            flags.push_str(&format!(" ({} skipped)", s.except.len()));
+        }
+        if !s.enabled {
+            flags.push_str(" disabled");
+        }
+        println!(
+            "{}  {}  {}-{}  {}{flags}",
+            s.id,
+            days.join(","),
+            s.start_time,
+            s.end_time,
+            s.mode
+        );
+    }
+}
+
+/// The `settings set early-end` spelling of a policy.
+fn early_end(policy: &EarlyEnd) -> String {
+    match policy {
+        EarlyEnd::None => "none".to_string(),
+        EarlyEnd::Password => "password".to_string(),
+        EarlyEnd::Wait { minutes } => format!("wait:{minutes}"),
+        EarlyEnd::Phrase { phrase } => format!("phrase:{phrase}"),
+    }
+}
+
+fn limit(v: Option<u32>) -> String {
+    v.map_or("none".to_string(), |n| n.to_string())
+}
+
+fn list_or_none(items: &[String]) -> String {
+    if items.is_empty() {
+        "none".to_string()
+    } else {
+        items.join(", ")
+    }
+}
+
+fn remaining(end_time: Option<u64>) -> String {
+    match end_time {
+        Some(end) => {
+            let ms = end.saturating_sub(config::now_ms());
+            format!("{} min remaining", ms.div_ceil(60_000))
+        }
+        None => "no end time".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::sandbox::Sandbox;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn ics(uids: &[&str]) -> calendar::Import {
+        let mut text = String::from("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n");
+        for uid in uids {
+            text += &format!(
+                "BEGIN:VEVENT\r\nUID:{uid}\r\nSUMMARY:Deep work\r\n\
+                 DTSTART:20260105T090000\r\nDTEND:20260105T100000\r\nEND:VEVENT\r\n"
+            );
+        }
+        text += "END:VCALENDAR\r\n";
+        calendar::parse(text.as_bytes(), &Default::default()).unwrap()
+    }
+
+    fn ids(schedules: &[SchedulePayload]) -> Vec<&str> {
+        schedules.iter().map(|s| s.id.as_str()).collect()
+    }
+
+    #[test]
+    fn reimport_replaces_schedules_from_the_same_file() {
+        let mut schedules = Vec::new();
+        merge_import(&mut schedules, ics(&["a", "b"]), "/cal/work.ics");
+        merge_import(&mut schedules, ics(&["x"]), "/cal/home.ics");
+        assert_eq!(ids(&schedules), ["ical:a", "ical:b", "ical:x"]);
+        assert_eq!(schedules[0].source.as_deref(), Some("/cal/work.ics"));
+
+        // "b" was deleted from work.ics; home.ics is untouched.
+        merge_import(&mut schedules, ics(&["a", "c"]), "/cal/work.ics");
+        assert_eq!(ids(&schedules), ["ical:x", "ical:a", "ical:c"]);
+    }
+
+    #[test]
+    fn chromium_launches_with_the_caller_origin() {
+        assert!(is_browser_launch(&args(&["chrome-extension://abcdef/"])));
+        assert!(is_browser_launch(&args(&[
+            "chrome-extension://abcdef/",
+            "--parent-window=1234"
+        ])));
+        assert!(is_browser_launch(&args(&["moz-extension://abcdef/"])));
+    }
+
+    #[test]
+    fn firefox_launches_with_manifest_and_extension_id() {
+        let sandbox = Sandbox::new();
+        let manifest = sandbox.root.join("com.focusblocker.native.json");
+        fs::write(&manifest, "{}").unwrap();
+        let manifest = manifest.to_string_lossy();
+
+        assert!(is_browser_launch(&args(&[&manifest, "focus@example.org"])));
+        assert!(!is_browser_launch(&args(&[&manifest])));
+        assert!(!is_browser_launch(&args(&[&manifest, "--json"])));
+        assert!(!is_browser_launch(&args(&[
+            "missing/manifest.json",
+            "focus@example.org"
+        ])));
+    }
+
+    #[test]
+    fn cli_invocations_are_not_browser_launches() {
+        assert!(!is_browser_launch(&args(&["status"])));
+        assert!(!is_browser_launch(&args(&["settings.json"])));
+        assert!(!is_browser_launch(&args(&["import", "settings.json"])));
+        assert!(!is_browser_launch(&args(&[
+            "status",
+            "chrome-extension://x/"
+        ])));
+    }
+
+    /// The `--json` document for `argv` after `request` has run.
+    fn json_for(argv: &[&str], request: Request) -> serde_json::Value {
+        let argv = ["focus-blocker-native", "--json"].iter().chain(argv);
+        let cli = Cli::try_parse_from(argv).unwrap();
+        let Response::Ok(reply) = handle_message(request).unwrap().0 else {
+            panic!("{:?} was refused", cli.command);
+        };
+        json_document(&cli.command, reply).unwrap()
+    }
+
+    fn keys(doc: &serde_json::Value) -> Vec<&String> {
+        doc.as_object().unwrap().keys().collect()
+    }
+
+    #[test]
+    fn session_commands_print_the_state() {
+        let _sandbox = Sandbox::new();
+        let start = Request::StartSession {
+            duration_minutes: Some(25),
+            scheduled_id: None,
+            locked: false,
+            mode: Some(Mode::Strict),
+            cycle: None,
+        };
+        let doc = json_for(&["start", "--mode", "strict"], start);
+        assert_eq!(doc["status"], "OK");
+        assert_eq!(doc["session"]["mode"], "strict");
+        assert_eq!(keys(&doc), keys(&state_document(None).unwrap()));
+    }
+
+    #[test]
+    fn stats_are_nested_in_the_state() {
+        let _sandbox = Sandbox::new();
+        let stats = Request::GetStats {
+            days: Some(3),
+            weeks: None,
+        };
+        let doc = json_for(&["stats", "--days", "3"], stats);
+        assert_eq!(doc["stats"]["days"].as_array().unwrap().len(), 3);
+        assert!(doc["blockedDomains"].is_array());
+    }
+
+    #[test]
+    fn history_export_is_one_document() {
+        let _sandbox = Sandbox::new();
+        let history = || Request::GetHistory {
+            from: None,
+            to: None,
+        };
+        handle_message(Request::StartSession {
+            duration_minutes: Some(25),
+            scheduled_id: None,
+            locked: false,
+            mode: None,
+            cycle: None,
+        })
+        .unwrap();
+        handle_message(Request::EndSession {
+            natural: false,
+            parent_pin: None,
+            confirmation: None,
+            password: None,
+        })
+        .unwrap();
+
+        let doc = json_for(&["history", "export", "--format", "csv"], history());
+        assert_eq!(doc["history"].as_array().unwrap().len(), 1);
+
+        let doc = json_for(&["history", "export", "--session-stats"], history());
+        assert!(doc["sessionStats"].is_object());
+        assert!(doc.get("history").is_none());
+    }
+
+    #[test]
+    fn recovery_codes_are_nested_in_the_state() {
+        let _sandbox = Sandbox::new();
+        let policy = config::Argon2Policy {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+            target_ms: 0,
+        };
+        config::update(|cfg| {
+            cfg.password_hash = Some(crate::password::hash_with("correct horse", &policy).unwrap());
+            cfg.argon2 = policy;
+        })
+        .unwrap();
+
+        let regenerate = Request::RegenerateRecoveryCodes {
+            password: Some("correct horse".into()),
+        };
+        let doc = json_for(&["password", "recovery-codes"], regenerate);
+        let codes = doc["recoveryCodes"].as_array().unwrap();
+        assert_eq!(
+            codes.len(),
+            doc["recoveryCodesLeft"].as_u64().unwrap() as usize
+        );
+        assert_eq!(doc["passwordSet"], true);
+    }
+}
@@ -7,38 +7,270 @@
 //! Mode-based state machine (v2):
 //!   session.mode: "off" | "precision" | "strict"
 //!   global_settings.default_mode: "precision" | "strict"
+//!
+//! Older on-disk schemas are upgraded by `migrations` before deserialization.
 
+use crate::migrations::{self, CURRENT_SCHEMA_VERSION};
 use crate::platform;
+use crate::schedule::Schedule;
 use crate::AppError;
+use chrono::{Local, NaiveDate};
 use fs2::FileExt;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::{self, File, OpenOptions};
-use std::path::PathBuf;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 // =========================================================================
 // Data model
 // =========================================================================
 
-#[derive(Serialize, Deserialize, Default, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
+    /// On-disk schema version — see `migrations`.
+    #[serde(default)]
+    pub schema_version: u32,
+    /// Admin password: quitting, settings and credential changes.
     #[serde(default)]
     pub password_hash: Option<String>,
+    /// Credentials that can only end or pause locked sessions. While none
+    /// is set, the admin password unlocks sessions instead; once one is,
+    /// only the PINs do.
+    #[serde(default)]
+    pub session_pins: Vec<SessionPin>,
+    /// Argon2 hashes of the unused one-time codes that reset the admin
+    /// password.
+    #[serde(default)]
+    pub recovery_codes: Vec<String>,
     #[serde(default)]
     pub blocked_domains: Vec<String>,
+    /// User-defined subdomains / companion domains per blocked domain,
+    /// on top of the built-in families in `domain_families`.
+    #[serde(default)]
+    pub domain_families: BTreeMap<String, Vec<String>>,
     #[serde(default)]
     pub session: Option<SessionState>,
     #[serde(default)]
     pub youtube_rules: Option<YoutubeRules>,
     #[serde(default)]
     pub global_settings: Option<GlobalSettings>,
+    /// Recurring sessions started by `restore` — see `schedule`.
+    #[serde(default)]
+    pub schedules: Vec<Schedule>,
+    /// Sessions started today, counted here so every browser shares it.
+    #[serde(default)]
+    pub daily_sessions: DailyCount,
+    /// Wrong passwords, PINs and recovery codes since the last correct
+    /// one of each — see `password::check`.
+    #[serde(default)]
+    pub failed_attempts: FailedAttempts,
+    /// Cost of new password, PIN and recovery-code hashes.
+    #[serde(default)]
+    pub argon2: Argon2Policy,
+    /// Set when `config.json` was unreadable and a backup was loaded in
+    /// its place; reported by GET_STATE and `status` until DISMISS_RECOVERY.
+    #[serde(default)]
+    pub recovered: Option<Recovery>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            schema_version: CURRENT_SCHEMA_VERSION,
+            password_hash: None,
+            session_pins: Vec::new(),
+            recovery_codes: Vec::new(),
+            blocked_domains: Vec::new(),
+            domain_families: BTreeMap::new(),
+            session: None,
+            youtube_rules: None,
+            global_settings: None,
+            schedules: Vec::new(),
+            daily_sessions: DailyCount::default(),
+            failed_attempts: FailedAttempts::default(),
+            argon2: Argon2Policy::default(),
+            recovered: None,
+        }
+    }
+}
+
+impl Config {
+    /// Mode of the current session, `Off` when there is none.
+    pub fn session_mode(&self) -> Mode {
+        self.session.as_ref().map_or(Mode::Off, |s| s.mode)
+    }
+
+    /// True if any schedule may start a session, so `restore` must keep running.
+    pub fn has_enabled_schedules(&self) -> bool {
+        self.schedules.iter().any(|s| s.enabled)
+    }
+
+    /// Addresses blocked domains are pointed at in the hosts file.
+    pub fn sink_addresses(&self) -> Vec<IpAddr> {
+        self.global_settings
+            .as_ref()
+            .map_or_else(default_sink_addresses, |gs| gs.sink_addresses.clone())
+    }
+
+    /// Sessions `START_SESSION` may begin per local day; `None` is unlimited.
+    pub fn daily_session_limit(&self) -> Option<u32> {
+        self.global_settings
+            .as_ref()
+            .map_or_else(default_daily_session_limit, |gs| gs.daily_session_limit)
+    }
+}
+
+/// Sessions started on one local date. A count left over from an earlier
+/// date reads as zero, so the day rolls over without anything resetting it.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct DailyCount {
+    #[serde(default)]
+    pub date: Option<NaiveDate>,
+    #[serde(default)]
+    pub count: u32,
+}
+
+impl DailyCount {
+    pub fn on(&self, today: NaiveDate) -> u32 {
+        if self.date == Some(today) {
+            self.count
+        } else {
+            0
+        }
+    }
+
+    pub fn increment(&mut self, today: NaiveDate) {
+        self.count = self.on(today) + 1;
+        self.date = Some(today);
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionPin {
+    /// Who the PIN was given to, e.g. "kids"; unique.
+    pub label: String,
+    pub hash: String,
+}
+
+/// Failure counts kept apart per credential, so a correct PIN doesn't
+/// clear the backoff built up against the admin password.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct FailedAttempts {
+    #[serde(default)]
+    pub admin: AttemptCount,
+    #[serde(default)]
+    pub session_pin: AttemptCount,
+    #[serde(default)]
+    pub recovery_code: AttemptCount,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct AttemptCount {
+    #[serde(default)]
+    pub count: u32,
+    /// Epoch ms of the latest failure.
+    #[serde(default)]
+    pub last_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Recovery {
+    /// Epoch ms.
+    pub at: u64,
+    /// File name of the backup that was used, e.g. `config.json.bak.2`.
+    pub backup: String,
+    /// Why `config.json` was rejected.
+    pub reason: String,
+}
+
+/// Argon2id parameters for new hashes. `setup` and `calibrate` benchmark
+/// `iterations` to take about `target_ms` on this machine; older, cheaper
+/// hashes are upgraded by `password::check` when they next verify.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Argon2Policy {
+    #[serde(default = "default_argon2_memory_kib")]
+    pub memory_kib: u32,
+    #[serde(default = "default_argon2_iterations")]
+    pub iterations: u32,
+    #[serde(default = "default_argon2_parallelism")]
+    pub parallelism: u32,
+    #[serde(default = "default_argon2_target_ms")]
+    pub target_ms: u64,
+}
+
+impl Default for Argon2Policy {
+    fn default() -> Self {
+        Self {
+            memory_kib: default_argon2_memory_kib(),
+            iterations: default_argon2_iterations(),
+            parallelism: default_argon2_parallelism(),
+            target_ms: default_argon2_target_ms(),
+        }
+    }
+}
+
+/// Today's date in the system timezone.
+pub fn local_today() -> NaiveDate {
+    Local::now().date_naive()
+}
+
+/// Enforcement mode of a session.
+///   - off:       no enforcement
+///   - precision: extension-only channel blocking (no hosts changes)
+///   - strict:    hosts-level domain blocking
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Off,
+    Precision,
+    Strict,
+}
+
+impl Mode {
+    /// Returns true if the mode represents an active session.
+    pub fn is_active(self) -> bool {
+        self != Mode::Off
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mode::Off => "off",
+            Mode::Precision => "precision",
+            Mode::Strict => "strict",
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Mode::Off),
+            "precision" => Ok(Mode::Precision),
+            "strict" => Ok(Mode::Strict),
+            _ => Err(format!("unknown mode '{s}' (expected precision or strict)")),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct SessionState {
-    /// "off" | "precision" | "strict" — replaces the old `active` boolean.
-    #[serde(default = "default_mode_off")]
-    pub mode: String,
+    /// Replaces the old `active` boolean.
+    #[serde(default)]
+    pub mode: Mode,
     #[serde(default)]
     pub start_time: Option<u64>, // epoch ms
     #[serde(default)]
@@ -47,27 +279,234 @@ pub struct SessionState {
     pub locked: bool,
     #[serde(default)]
     pub scheduled_id: Option<String>,
+    /// Unique per session; ties tamper events and history together.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Hosts-file tamper detections during this session.
+    #[serde(default)]
+    pub tamper_count: u32,
+    /// Set while paused: time left when the pause began. `end_time` is
+    /// cleared for the duration of the pause.
+    #[serde(default)]
+    pub paused_remaining_ms: Option<u64>,
+    #[serde(default)]
+    pub paused_at: Option<u64>,
+    #[serde(default)]
+    pub pause_count: u32,
+    /// Total length of finished pauses.
+    #[serde(default)]
+    pub paused_ms: u64,
+    /// When an early end was first asked for under a `Wait` friction policy.
+    #[serde(default)]
+    pub end_requested_at: Option<u64>,
+    /// Work/break alternation; `None` is one continuous work span.
+    #[serde(default)]
+    pub cycle: Option<Cycle>,
+}
+
+impl SessionState {
+    pub fn is_paused(&self) -> bool {
+        self.paused_remaining_ms.is_some()
+    }
+
+    /// Active and not yet past its end. A paused session is still running.
+    pub fn is_running(&self, now: u64) -> bool {
+        self.mode.is_active() && self.end_time.is_none_or(|end| now < end)
+    }
+
+    /// Stop the clock, keeping the remaining time.
+    pub fn pause(&mut self, now: u64) {
+        let remaining = self.end_time.map_or(0, |end| end.saturating_sub(now));
+        self.paused_remaining_ms = Some(remaining);
+        self.paused_at = Some(now);
+        self.end_time = None;
+        self.pause_count += 1;
+    }
+
+    /// Push the end back by `ms`; a paused session keeps it as remaining
+    /// time. `start_time` is left alone.
+    pub fn extend(&mut self, ms: u64) {
+        if let Some(remaining) = self.paused_remaining_ms.as_mut() {
+            *remaining += ms;
+        } else if let Some(end) = self.end_time.as_mut() {
+            *end += ms;
+        }
+    }
 
-    // Legacy fields — read for migration, never written back.
-    #[serde(default, skip_serializing)]
-    active: Option<bool>,
+    /// Restart the clock with the time that was left when paused.
+    pub fn resume(&mut self, now: u64) {
+        if let Some(remaining) = self.paused_remaining_ms.take() {
+            self.end_time = Some(now + remaining);
+        }
+        if let Some(at) = self.paused_at {
+            let paused = now.saturating_sub(at);
+            self.paused_ms += paused;
+            if let Some(cycle) = self.cycle.as_mut() {
+                cycle.phase_end += paused;
+            }
+        }
+        self.paused_at = None;
+    }
+
+    /// True during a cycle break, when blocking is lifted.
+    pub fn in_break(&self) -> bool {
+        self.cycle.as_ref().is_some_and(|c| c.phase.is_break())
+    }
+
+    /// Move the cycle on to the phase that is current at `now`. Returns
+    /// true if anything changed. The clock is stopped while paused.
+    pub fn advance_cycle(&mut self, now: u64) -> bool {
+        if self.is_paused() || !self.mode.is_active() {
+            return false;
+        }
+        self.cycle.as_mut().is_some_and(|c| c.advance(now))
+    }
 }
 
-fn default_mode_off() -> String {
-    "off".to_string()
+/// Pomodoro-style cycle: work, break, work, break, ... with every
+/// `long_break_every`-th break a long one. The session still ends at
+/// `end_time`, whatever phase it is in.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Cycle {
+    pub work_minutes: u32,
+    pub break_minutes: u32,
+    #[serde(default)]
+    pub long_break_minutes: u32,
+    /// 0 = no long breaks.
+    #[serde(default)]
+    pub long_break_every: u32,
+    #[serde(default)]
+    pub phase: Phase,
+    /// End of the current phase, epoch ms. Pushed back on resume.
+    #[serde(default)]
+    pub phase_end: u64,
+    /// Work phases finished so far.
+    #[serde(default)]
+    pub completed: u32,
+    /// Total length of the breaks finished so far, ms.
+    #[serde(default)]
+    pub break_ms: u64,
 }
 
-impl Default for SessionState {
-    fn default() -> Self {
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    #[default]
+    Work,
+    Break,
+    LongBreak,
+}
+
+impl Phase {
+    pub fn is_break(self) -> bool {
+        self != Phase::Work
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Phase::Work => "work",
+            Phase::Break => "break",
+            Phase::LongBreak => "long break",
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Cycle {
+    /// A cycle whose first work phase starts at `now`.
+    pub fn new(
+        work_minutes: u32,
+        break_minutes: u32,
+        long_break_minutes: u32,
+        long_break_every: u32,
+        now: u64,
+    ) -> Self {
         Self {
-            mode: "off".to_string(),
-            start_time: None,
-            end_time: None,
-            locked: false,
-            scheduled_id: None,
-            active: None,
+            work_minutes,
+            break_minutes,
+            long_break_minutes,
+            long_break_every,
+            phase: Phase::Work,
+            phase_end: now + minutes_ms(work_minutes),
+            completed: 0,
+            break_ms: 0,
         }
     }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.work_minutes == 0 || self.break_minutes == 0 {
+            return Err("work and break phases must be at least 1 minute".into());
+        }
+        if self.long_break_every > 0 && self.long_break_minutes == 0 {
+            return Err("long break must be at least 1 minute".into());
+        }
+        Ok(())
+    }
+
+    /// Step through every phase that ended by `now`.
+    fn advance(&mut self, now: u64) -> bool {
+        let mut changed = false;
+        while now >= self.phase_end {
+            self.phase = match self.phase {
+                Phase::Work => {
+                    self.completed += 1;
+                    let long = self.long_break_every > 0
+                        && self.completed.is_multiple_of(self.long_break_every);
+                    if long {
+                        Phase::LongBreak
+                    } else {
+                        Phase::Break
+                    }
+                }
+                Phase::Break | Phase::LongBreak => {
+                    self.break_ms += minutes_ms(self.phase_minutes());
+                    Phase::Work
+                }
+            };
+            self.phase_end += minutes_ms(self.phase_minutes());
+            changed = true;
+        }
+        changed
+    }
+
+    /// Length of the current phase. A hand-edited zero counts as a minute,
+    /// which would otherwise make `advance` spin forever.
+    fn phase_minutes(&self) -> u32 {
+        let minutes = match self.phase {
+            Phase::Work => self.work_minutes,
+            Phase::Break => self.break_minutes,
+            Phase::LongBreak => self.long_break_minutes,
+        };
+        minutes.max(1)
+    }
+
+    /// Break time taken up to `at`, not counting pauses. `paused_at` is the
+    /// start of a pause still in progress, which stopped the clock.
+    pub fn break_ms_at(&self, at: u64, paused_at: Option<u64>) -> u64 {
+        let at = paused_at.map_or(at, |p| p.min(at));
+        let mut cycle = self.clone();
+        cycle.advance(at);
+        let current = if cycle.phase.is_break() {
+            minutes_ms(cycle.phase_minutes()).saturating_sub(cycle.phase_end.saturating_sub(at))
+        } else {
+            0
+        };
+        cycle.break_ms + current
+    }
+}
+
+fn minutes_ms(minutes: u32) -> u64 {
+    u64::from(minutes) * 60_000
+}
+
+/// Identifier for a session started at `start_ms`.
+pub fn new_session_id(start_ms: u64) -> String {
+    format!("{start_ms:x}-{:x}", std::process::id())
 }
 
 #[derive(Serialize, Deserialize, Default, Clone)]
@@ -80,47 +519,188 @@ pub struct YoutubeRules {
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct GlobalSettings {
-    /// "precision" | "strict" — replaces the old `strict_mode` boolean.
+    /// Precision or strict — replaces the old `strict_mode` boolean.
     #[serde(default = "default_mode_precision")]
-    pub default_mode: String,
+    pub default_mode: Mode,
     /// Only applies in precision mode.
     #[serde(default)]
     pub block_all_channels: bool,
     #[serde(default = "default_session_duration")]
     pub session_duration_minutes: u32,
+    /// Hosts-file sink addresses — at least one IPv4 and one IPv6, so
+    /// neither A nor AAAA lookups escape strict mode.
+    #[serde(default = "default_sink_addresses")]
+    pub sink_addresses: Vec<IpAddr>,
+    #[serde(default)]
+    pub tamper_policy: TamperPolicy,
+    #[serde(default)]
+    pub calendar_import: CalendarImport,
+    #[serde(default)]
+    pub pause_policy: PausePolicy,
+    #[serde(default)]
+    pub early_end: EarlyEnd,
+    /// `null` for no limit.
+    #[serde(default = "default_daily_session_limit")]
+    pub daily_session_limit: Option<u32>,
+}
+
+/// Friction on ending a session before its time is up. Checked by the
+/// host on every `END_SESSION` that isn't a natural expiry, on top of the
+/// PIN for locked sessions.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum EarlyEnd {
+    #[default]
+    None,
+    /// The first request starts a countdown; ending is allowed once it has
+    /// run out, for `EARLY_END_CONFIRM_WINDOW_MS`.
+    Wait { minutes: u32 },
+    /// The caller has to type `phrase` back exactly.
+    Phrase { phrase: String },
+    /// The admin password is required.
+    Password,
+}
+
+/// How long an elapsed `EarlyEnd::Wait` stays valid before it must be
+/// requested again.
+pub const EARLY_END_CONFIRM_WINDOW_MS: u64 = 5 * 60 * 1000;
+
+impl EarlyEnd {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            EarlyEnd::Wait { minutes: 0 } => Err("early-end wait must be at least 1 minute".into()),
+            EarlyEnd::Phrase { phrase } if phrase.trim().is_empty() => {
+                Err("early-end phrase must not be empty".into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Limits on pausing a session; `None` means no limit.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PausePolicy {
+    /// Pauses allowed per session.
+    #[serde(default)]
+    pub max_pauses: Option<u32>,
+    /// A pause longer than this is resumed automatically by `restore`.
+    #[serde(default)]
+    pub max_pause_minutes: Option<u32>,
+}
+
+impl Default for PausePolicy {
+    fn default() -> Self {
+        Self {
+            max_pauses: Some(3),
+            max_pause_minutes: Some(15),
+        }
+    }
+}
+
+/// Escalation applied to the running session each time the hosts block is
+/// found tampered with. The default only records the event.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct TamperPolicy {
+    /// Push `end_time` back by this many minutes per tamper (0 = off).
+    #[serde(default)]
+    pub extend_minutes: u32,
+    /// Force the session to `locked` once this many tampers have been seen.
+    #[serde(default)]
+    pub lock_after: Option<u32>,
+}
+
+/// Which events `schedules import` turns into sessions. Patterns are
+/// regular expressions matched against an event's summary and categories;
+/// strict is tried first, and an empty pattern matches nothing.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CalendarImport {
+    #[serde(default = "default_strict_pattern")]
+    pub strict_pattern: String,
+    #[serde(default = "default_precision_pattern")]
+    pub precision_pattern: String,
+}
+
+impl Default for CalendarImport {
+    fn default() -> Self {
+        Self {
+            strict_pattern: default_strict_pattern(),
+            precision_pattern: default_precision_pattern(),
+        }
+    }
+}
 
-    // Legacy fields — read for migration, never written back.
-    #[serde(default, skip_serializing)]
-    strict_mode: Option<bool>,
-    #[serde(default, skip_serializing)]
-    block_youtube_fallback: Option<bool>,
+fn default_strict_pattern() -> String {
+    r"(?i)\bdeep[ -]?work\b|\bstrict\b".into()
 }
 
-fn default_mode_precision() -> String {
-    "precision".to_string()
+fn default_precision_pattern() -> String {
+    r"(?i)\bfocus\b".into()
+}
+
+fn default_mode_precision() -> Mode {
+    Mode::Precision
 }
 
 fn default_session_duration() -> u32 {
     30
 }
 
+/// The extension's `FREE_DAILY_LIMIT`.
+fn default_daily_session_limit() -> Option<u32> {
+    Some(2)
+}
+
+/// What hashes used before the policy existed, so old installs don't
+/// rehash until `setup` calibrates.
+fn default_argon2_memory_kib() -> u32 {
+    argon2::Params::DEFAULT_M_COST
+}
+
+fn default_argon2_iterations() -> u32 {
+    argon2::Params::DEFAULT_T_COST
+}
+
+fn default_argon2_parallelism() -> u32 {
+    argon2::Params::DEFAULT_P_COST
+}
+
+fn default_argon2_target_ms() -> u64 {
+    500
+}
+
+fn default_sink_addresses() -> Vec<IpAddr> {
+    vec![
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(Ipv6Addr::LOCALHOST),
+    ]
+}
+
+/// Check that a sink list covers both address families.
+pub fn validate_sink_addresses(sinks: &[IpAddr]) -> Result<(), AppError> {
+    if !sinks.iter().any(IpAddr::is_ipv4) || !sinks.iter().any(IpAddr::is_ipv6) {
+        return Err(AppError::Config(
+            "Sink addresses must include at least one IPv4 and one IPv6 address".into(),
+        ));
+    }
+    Ok(())
+}
+
 impl Default for GlobalSettings {
     fn default() -> Self {
         Self {
-            default_mode: "precision".to_string(),
+            default_mode: Mode::Precision,
             block_all_channels: false,
             session_duration_minutes: 30,
-            strict_mode: None,
-            block_youtube_fallback: None,
+            sink_addresses: default_sink_addresses(),
+            tamper_policy: TamperPolicy::default(),
+            calendar_import: CalendarImport::default(),
+            pause_policy: PausePolicy::default(),
+            early_end: EarlyEnd::default(),
+            daily_session_limit: default_daily_session_limit(),
         }
     }
 }
 
-/// Returns true if the mode value represents an active session.
-pub fn is_mode_active(mode: &str) -> bool {
-    mode == "precision" || mode == "strict"
-}
-
 // =========================================================================
 // Path helpers
 // =========================================================================
@@ -129,6 +709,14 @@ fn config_path() -> PathBuf {
     platform::config_dir().join("config.json")
 }
 
+fn backup_path(n: usize) -> PathBuf {
+    platform::config_dir().join(format!("config.json.bak.{n}"))
+}
+
+fn lock_path() -> PathBuf {
+    platform::config_dir().join("config.lock")
+}
+
 /// Current time as milliseconds since Unix epoch.
 pub fn now_ms() -> u64 {
     SystemTime::now()
@@ -137,139 +725,415 @@ pub fn now_ms() -> u64 {
         .as_millis() as u64
 }
 
-// =========================================================================
-// Migration
-// =========================================================================
-
-/// Migrate legacy boolean fields to mode-based schema.
-/// Called after deserialization — mutates in place.
-fn migrate(config: &mut Config) {
-    // Migrate session: if mode is default "off" but legacy `active` is true
-    if let Some(ref mut session) = config.session {
-        if session.mode == "off" {
-            if let Some(true) = session.active {
-                // Derive mode from global_settings.strict_mode
-                let was_strict = config
-                    .global_settings
-                    .as_ref()
-                    .and_then(|gs| gs.strict_mode)
-                    .unwrap_or(false);
-                session.mode = if was_strict {
-                    "strict".to_string()
-                } else {
-                    "precision".to_string()
-                };
-            }
-        }
-        // Clear legacy field
-        session.active = None;
-    }
-
-    // Migrate global_settings: if legacy strict_mode exists
-    if let Some(ref mut gs) = config.global_settings {
-        if let Some(strict) = gs.strict_mode {
-            if gs.default_mode == "precision" && strict {
-                gs.default_mode = "strict".to_string();
-            }
-            gs.strict_mode = None;
-        }
-        if let Some(fallback) = gs.block_youtube_fallback {
-            // block_youtube_fallback is removed — strict mode IS the fallback.
-            // If it was true and block_all_channels is false, leave it.
-            // The behavior is now handled by mode, not a separate flag.
-            gs.block_youtube_fallback = None;
-            let _ = fallback; // consumed
-        }
-    }
-}
-
 // =========================================================================
 // Load / save with file locking
 // =========================================================================
+//
+// Writes never touch `config.json` in place: the new content goes to a temp
+// file which is fsynced and renamed over the original, so a crash leaves
+// either the old or the new file, never a truncated one. An update that
+// changes nothing writes nothing. Before a write the previous (valid) file
+// is rotated into `config.json.bak.1..N`, at most once per
+// `BACKUP_INTERVAL`, so a burst of small writes can't push every older
+// generation out.
+//
+// Because the file is replaced on every write, locks are taken on a separate
+// `config.lock` file rather than on `config.json` itself.
+
+/// Number of rolling `config.json.bak.*` snapshots to keep.
+const BACKUP_COUNT: usize = 5;
+/// Minimum age of `config.json.bak.1` before it is rotated again.
+const BACKUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
 
 /// Load config from disk, returning defaults if the file doesn't exist.
-/// Runs migration for legacy fields.
+/// Runs schema migrations. Falls back to the newest valid backup
+/// if `config.json` is corrupt.
 pub fn load() -> Result<Config, AppError> {
-    let path = config_path();
-    if !path.exists() {
+    if !platform::config_dir().exists() {
         return Ok(Config::default());
     }
 
-    let file = File::open(&path)?;
-    file.lock_shared().map_err(|e| AppError::Config(format!("Shared lock failed: {e}")))?;
-
-    // Read while the shared lock is held by `file`.
-    let data = fs::read_to_string(&path)?;
+    let lock = open_lock()?;
+    lock.lock_shared()
+        .map_err(|e| AppError::Config(format!("Shared lock failed: {e}")))?;
 
-    file.unlock().ok();
+    let result = read_locked();
 
-    let mut config: Config =
-        serde_json::from_str(&data).map_err(|e| AppError::Config(format!("Invalid config: {e}")))?;
-    migrate(&mut config);
-    Ok(config)
+    lock.unlock().ok();
+    result
 }
 
-/// Persist config to disk, creating the parent directory if needed.
-pub fn save(config: &Config) -> Result<(), AppError> {
-    let dir = platform::config_dir();
-    fs::create_dir_all(&dir)?;
+/// Atomic read-modify-write with exclusive file lock.
+///
+/// The closure receives a mutable reference to the current config.
+/// After the closure returns, the modified config is saved to disk.
+/// A corrupt config with no valid backup is an error — it is never
+/// silently replaced with defaults.
+pub fn update<F>(f: F) -> Result<Config, AppError>
+where
+    F: FnOnce(&mut Config),
+{
+    let lock = open_lock()?;
+    lock.lock_exclusive()
+        .map_err(|e| AppError::Config(format!("Exclusive lock failed: {e}")))?;
 
-    let path = config_path();
-    let file = OpenOptions::new()
+    let result = read_locked().and_then(|mut config| {
+        f(&mut config);
+        write_locked(&config)?;
+        Ok(config)
+    });
+
+    lock.unlock().ok();
+    result
+}
+
+/// Open (creating if needed) the lock file guarding the config directory.
+fn open_lock() -> Result<File, AppError> {
+    fs::create_dir_all(platform::config_dir())?;
+    Ok(OpenOptions::new()
+        .read(true)
         .write(true)
         .create(true)
         .truncate(false)
-        .open(&path)?;
+        .open(lock_path())?)
+}
 
-    file.lock_exclusive()
-        .map_err(|e| AppError::Config(format!("Exclusive lock failed: {e}")))?;
+/// Read `config.json`, falling back to backups if it is corrupt.
+/// Caller must hold the lock.
+fn read_locked() -> Result<Config, AppError> {
+    let err = match read_file(&config_path()) {
+        Ok(Some(config)) => return Ok(config),
+        // A missing file means a fresh install (or a deliberate reset).
+        Ok(None) => return Ok(Config::default()),
+        // Never fall back to an older backup when the file is simply newer
+        // than we understand — the next write would downgrade it.
+        Err(ReadError::Unsupported(e)) => return Err(e),
+        Err(ReadError::Corrupt(e)) => e,
+    };
+
+    for n in 1..=BACKUP_COUNT {
+        let path = backup_path(n);
+        if let Ok(Some(mut config)) = read_file(&path) {
+            eprintln!(
+                "[FocusBlocker] {err}; recovered config from {}",
+                path.display()
+            );
+            config.recovered = Some(Recovery {
+                at: now_ms(),
+                backup: format!("config.json.bak.{n}"),
+                reason: err,
+            });
+            return Ok(config);
+        }
+    }
+
+    Err(AppError::Config(format!("{err}; no valid backup found")))
+}
+
+enum ReadError {
+    /// Unreadable, truncated or malformed — a backup may be used instead.
+    Corrupt(String),
+    /// Written by a newer binary — must not be replaced.
+    Unsupported(AppError),
+}
+
+/// Parse and migrate a config file. `Ok(None)` if it doesn't exist.
+fn read_file(path: &Path) -> Result<Option<Config>, ReadError> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(ReadError::Corrupt(format!(
+                "Cannot read {}: {e}",
+                path.display()
+            )))
+        }
+    };
+
+    parse(&data)
+        .map(Some)
+        .map_err(|e| match e {
+            ReadError::Corrupt(e) => {
+                ReadError::Corrupt(format!("Invalid config {}: {e}", path.display()))
+            }
+            other => other,
+        })
+}
+
+/// Migrate a raw config document to the current schema and deserialize it.
+fn parse(data: &str) -> Result<Config, ReadError> {
+    let mut doc: serde_json::Value =
+        serde_json::from_str(data).map_err(|e| ReadError::Corrupt(e.to_string()))?;
+
+    let newer = doc
+        .get("schema_version")
+        .and_then(|v| v.as_u64())
+        .is_some_and(|v| v > CURRENT_SCHEMA_VERSION as u64);
+    migrations::migrate(&mut doc).map_err(|e| {
+        if newer {
+            ReadError::Unsupported(e)
+        } else {
+            ReadError::Corrupt(e.to_string())
+        }
+    })?;
+
+    serde_json::from_value(doc).map_err(|e| ReadError::Corrupt(e.to_string()))
+}
 
+/// Rotate backups if due and atomically replace `config.json`, unless it
+/// already holds exactly this config. Caller must hold the exclusive lock.
+fn write_locked(config: &Config) -> Result<(), AppError> {
+    let path = config_path();
     let data = serde_json::to_string_pretty(config)?;
-    // Truncate and write while holding the lock.
-    fs::write(&path, &data)?;
 
-    file.unlock().ok();
+    if let Ok(previous) = fs::read_to_string(&path) {
+        if previous == data {
+            return Ok(());
+        }
+        // Only snapshot a file that parses — a corrupt file must not push
+        // the good backups out of the rotation.
+        if backup_due() && parse(&previous).is_ok() {
+            rotate_backups()?;
+            platform::write_atomic(&backup_path(1), &previous)?;
+        }
+    }
+
+    platform::write_atomic(&path, &data)?;
     Ok(())
 }
 
-/// Atomic read-modify-write with exclusive file lock.
-///
-/// The closure receives a mutable reference to the current config.
-/// After the closure returns, the modified config is saved to disk.
-pub fn update<F>(f: F) -> Result<Config, AppError>
-where
-    F: FnOnce(&mut Config),
-{
-    let dir = platform::config_dir();
-    fs::create_dir_all(&dir)?;
+/// True unless `config.json.bak.1` was written within `BACKUP_INTERVAL`.
+fn backup_due() -> bool {
+    fs::metadata(backup_path(1))
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.elapsed().ok())
+        .is_none_or(|age| age >= BACKUP_INTERVAL)
+}
 
-    let path = config_path();
+/// Shift `config.json.bak.N-1` → `.bak.N`, dropping the oldest.
+fn rotate_backups() -> Result<(), AppError> {
+    for n in (1..BACKUP_COUNT).rev() {
+        let from = backup_path(n);
+        if from.exists() {
+            fs::rename(&from, backup_path(n + 1))?;
+        }
+    }
+    Ok(())
+}
 
-    // Ensure the file exists before locking.
-    if !path.exists() {
-        fs::write(&path, "{}")?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: u64 = 60_000;
+
+    fn cycling_session(cycle: Cycle) -> SessionState {
+        SessionState {
+            mode: Mode::Strict,
+            start_time: Some(0),
+            end_time: Some(240 * MIN),
+            cycle: Some(cycle),
+            ..Default::default()
+        }
     }
 
-    let file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(&path)?;
+    #[test]
+    fn cycle_alternates_with_long_break() {
+        let mut s = cycling_session(Cycle::new(25, 5, 15, 2, 0));
 
-    file.lock_exclusive()
-        .map_err(|e| AppError::Config(format!("Exclusive lock failed: {e}")))?;
+        assert!(!s.advance_cycle(24 * MIN));
+        assert!(s.advance_cycle(25 * MIN));
+        assert!(s.in_break());
+
+        // Work 30..55, then the second break is the long one.
+        s.advance_cycle(55 * MIN);
+        let cycle = s.cycle.as_ref().unwrap();
+        assert_eq!(cycle.phase, Phase::LongBreak);
+        assert_eq!(cycle.completed, 2);
+        assert_eq!(cycle.phase_end, 70 * MIN);
+    }
+
+    #[test]
+    fn cycle_clock_stops_while_paused() {
+        let mut s = cycling_session(Cycle::new(25, 5, 0, 0, 0));
+
+        s.pause(10 * MIN);
+        assert!(!s.advance_cycle(40 * MIN));
+        s.resume(40 * MIN);
+
+        // 15 minutes of work were left when paused.
+        assert!(!s.advance_cycle(54 * MIN));
+        assert!(s.advance_cycle(55 * MIN));
+        assert_eq!(s.cycle.as_ref().unwrap().phase, Phase::Break);
+    }
+
+    #[test]
+    fn resume_pushes_the_end_back_by_the_pause() {
+        let mut s = SessionState {
+            mode: Mode::Strict,
+            start_time: Some(0),
+            end_time: Some(60 * MIN),
+            ..Default::default()
+        };
+
+        s.pause(20 * MIN);
+        assert!(s.is_paused());
+        assert_eq!(s.end_time, None);
+        // Extending while paused adds to the time left.
+        s.extend(5 * MIN);
+        s.resume(50 * MIN);
+        assert!(!s.is_paused());
+        assert_eq!(s.end_time, Some(95 * MIN));
+        assert_eq!(s.paused_ms, 30 * MIN);
+
+        s.pause(60 * MIN);
+        s.resume(70 * MIN);
+        assert_eq!(s.end_time, Some(105 * MIN));
+        assert_eq!(s.paused_ms, 40 * MIN);
+        assert_eq!(s.pause_count, 2);
+    }
+
+    #[test]
+    fn resume_without_pause_changes_nothing() {
+        let mut s = SessionState {
+            mode: Mode::Strict,
+            end_time: Some(60 * MIN),
+            ..Default::default()
+        };
+        s.resume(30 * MIN);
+        assert_eq!(s.end_time, Some(60 * MIN));
+        assert_eq!(s.paused_ms, 0);
+    }
+
+    #[test]
+    fn sinks_must_cover_both_families() {
+        let v4: IpAddr = "0.0.0.0".parse().unwrap();
+        let v6: IpAddr = "::".parse().unwrap();
+
+        assert!(validate_sink_addresses(&[v4, v6]).is_ok());
+        assert!(validate_sink_addresses(&default_sink_addresses()).is_ok());
+        assert!(validate_sink_addresses(&[v4]).is_err());
+        assert!(validate_sink_addresses(&[v6, v6]).is_err());
+        assert!(validate_sink_addresses(&[]).is_err());
+    }
+
+    fn block(domain: &str) -> Result<Config, AppError> {
+        let domain = domain.to_string();
+        update(|cfg| cfg.blocked_domains = vec![domain])
+    }
 
-    // Read current state under the lock.
-    let data = fs::read_to_string(&path).unwrap_or_else(|_| "{}".to_string());
+    fn blocked_in(path: &Path) -> Vec<String> {
+        let data = fs::read_to_string(path).unwrap();
+        parse(&data).ok().unwrap().blocked_domains
+    }
+
+    /// Pretend `config.json.bak.1` was written a whole interval ago.
+    fn age_backup() {
+        let then = SystemTime::now() - BACKUP_INTERVAL;
+        File::options()
+            .write(true)
+            .open(backup_path(1))
+            .unwrap()
+            .set_modified(then)
+            .unwrap();
+    }
+
+    #[test]
+    fn writes_leave_no_temp_files() {
+        let _sandbox = platform::sandbox::Sandbox::new();
+        block("a.com").unwrap();
+        block("b.com").unwrap();
+
+        let mut names: Vec<String> = fs::read_dir(platform::config_dir())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["config.json", "config.json.bak.1", "config.lock"]);
+        assert_eq!(blocked_in(&config_path()), ["b.com"]);
+        assert_eq!(blocked_in(&backup_path(1)), ["a.com"]);
+    }
+
+    #[test]
+    fn unchanged_config_is_not_rewritten() {
+        let _sandbox = platform::sandbox::Sandbox::new();
+        block("a.com").unwrap();
+        let written = fs::metadata(config_path()).unwrap().modified().unwrap();
 
-    let mut config: Config =
-        serde_json::from_str(&data).unwrap_or_default();
+        update(|_| {}).unwrap();
+        block("a.com").unwrap();
 
-    migrate(&mut config);
-    f(&mut config);
+        assert!(!backup_path(1).exists());
+        let after = fs::metadata(config_path()).unwrap().modified().unwrap();
+        assert_eq!(written, after);
+    }
 
-    let output = serde_json::to_string_pretty(&config)?;
-    fs::write(&path, &output)?;
+    #[test]
+    fn backups_rotate_at_most_once_per_interval() {
+        let _sandbox = platform::sandbox::Sandbox::new();
+        block("a.com").unwrap();
+        block("b.com").unwrap();
+        // bak.1 is fresh, so this burst doesn't rotate.
+        block("c.com").unwrap();
+        block("d.com").unwrap();
+        assert_eq!(blocked_in(&backup_path(1)), ["a.com"]);
+        assert!(!backup_path(2).exists());
+
+        age_backup();
+        block("e.com").unwrap();
+        assert_eq!(blocked_in(&backup_path(1)), ["d.com"]);
+        assert_eq!(blocked_in(&backup_path(2)), ["a.com"]);
+        assert_eq!(blocked_in(&config_path()), ["e.com"]);
+    }
 
-    file.unlock().ok();
-    Ok(config)
+    #[test]
+    fn corrupt_config_falls_back_to_newest_valid_backup() {
+        let _sandbox = platform::sandbox::Sandbox::new();
+        block("a.com").unwrap();
+        block("b.com").unwrap();
+        age_backup();
+        block("c.com").unwrap();
+        // config.json: c, bak.1: b, bak.2: a. Break the first two.
+        fs::write(config_path(), "{\"blocked_domains\": [").unwrap();
+        fs::write(backup_path(1), "").unwrap();
+
+        let cfg = load().unwrap();
+        assert_eq!(cfg.blocked_domains, ["a.com"]);
+        let recovered = cfg.recovered.unwrap();
+        assert_eq!(recovered.backup, "config.json.bak.2");
+        assert!(recovered.reason.contains("config.json"));
+
+        // The next write repairs the file and keeps the record until it is
+        // dismissed.
+        update(|_| {}).unwrap();
+        let data = fs::read_to_string(config_path()).unwrap();
+        let cfg = parse(&data).ok().unwrap();
+        assert_eq!(cfg.blocked_domains, ["a.com"]);
+        assert!(cfg.recovered.is_some());
+    }
+
+    #[test]
+    fn corrupt_config_without_backup_is_an_error() {
+        let _sandbox = platform::sandbox::Sandbox::new();
+        fs::create_dir_all(platform::config_dir()).unwrap();
+        fs::write(config_path(), "not json").unwrap();
+
+        assert!(load().is_err());
+        assert!(update(|cfg| cfg.blocked_domains.clear()).is_err());
+        assert_eq!(fs::read_to_string(config_path()).unwrap(), "not json");
+    }
+
+    #[test]
+    fn newer_config_is_not_replaced_by_a_backup() {
+        let _sandbox = platform::sandbox::Sandbox::new();
+        block("a.com").unwrap();
+        block("b.com").unwrap();
+        let newer = format!("{{\"schema_version\": {}}}", CURRENT_SCHEMA_VERSION + 1);
+        fs::write(config_path(), &newer).unwrap();
+
+        assert!(load().is_err());
+        assert!(block("c.com").is_err());
+        assert_eq!(fs::read_to_string(config_path()).unwrap(), newer);
+    }
 }
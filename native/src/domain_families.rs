@@ -0,0 +1,223 @@
+//! Domain "family" expansion for hosts-level blocking.
+//!
+//! Blocking `youtube.com` alone leaves `m.youtube.com`, `youtu.be`, the
+//! thumbnail CDN and friends reachable. Each blocked domain is expanded to
+//! its `www.` variant, a built-in list of known subdomains and companion
+//! domains, and any user-defined family from `Config.domain_families`.
+//!
+//! The hosts file has no wildcards, so randomised CDN hostnames (e.g.
+//! `rr1---sn-abc.googlevideo.com`) cannot be covered — only the fixed
+//! entry points that hand them out.
+
+use std::collections::BTreeMap;
+
+/// Known subdomains and companion domains for popular sites.
+const BUILTIN_FAMILIES: &[(&str, &[&str])] = &[
+    (
+        "youtube.com",
+        &[
+            "m.youtube.com",
+            "music.youtube.com",
+            "gaming.youtube.com",
+            "youtubei.googleapis.com",
+            "youtube-nocookie.com",
+            "www.youtube-nocookie.com",
+            "youtu.be",
+            "www.youtu.be",
+            "i.ytimg.com",
+            "s.ytimg.com",
+            "i9.ytimg.com",
+            "yt3.ggpht.com",
+            "redirector.googlevideo.com",
+            "manifest.googlevideo.com",
+        ],
+    ),
+    (
+        "reddit.com",
+        &[
+            "old.reddit.com",
+            "new.reddit.com",
+            "np.reddit.com",
+            "m.reddit.com",
+            "i.reddit.com",
+            "oauth.reddit.com",
+            "gql.reddit.com",
+            "redd.it",
+            "i.redd.it",
+            "v.redd.it",
+            "preview.redd.it",
+            "external-preview.redd.it",
+            "www.redditstatic.com",
+        ],
+    ),
+    (
+        "twitter.com",
+        &[
+            "mobile.twitter.com",
+            "api.twitter.com",
+            "x.com",
+            "www.x.com",
+            "api.x.com",
+            "t.co",
+            "pbs.twimg.com",
+            "abs.twimg.com",
+            "video.twimg.com",
+        ],
+    ),
+    (
+        "x.com",
+        &[
+            "api.x.com",
+            "twitter.com",
+            "www.twitter.com",
+            "mobile.twitter.com",
+            "t.co",
+            "pbs.twimg.com",
+            "abs.twimg.com",
+            "video.twimg.com",
+        ],
+    ),
+    (
+        "facebook.com",
+        &[
+            "m.facebook.com",
+            "web.facebook.com",
+            "mbasic.facebook.com",
+            "touch.facebook.com",
+            "fb.com",
+            "www.fb.com",
+            "static.xx.fbcdn.net",
+        ],
+    ),
+    (
+        "instagram.com",
+        &[
+            "i.instagram.com",
+            "graph.instagram.com",
+            "help.instagram.com",
+            "static.cdninstagram.com",
+            "scontent.cdninstagram.com",
+        ],
+    ),
+    (
+        "tiktok.com",
+        &[
+            "m.tiktok.com",
+            "vm.tiktok.com",
+            "vt.tiktok.com",
+            "t.tiktok.com",
+        ],
+    ),
+    (
+        "twitch.tv",
+        &[
+            "m.twitch.tv",
+            "clips.twitch.tv",
+            "player.twitch.tv",
+            "gql.twitch.tv",
+            "static-cdn.jtvnw.net",
+            "usher.ttvnw.net",
+        ],
+    ),
+    (
+        "netflix.com",
+        &[
+            "m.netflix.com",
+            "assets.nflxext.com",
+            "api-global.netflix.com",
+        ],
+    ),
+];
+
+/// Family members for a single domain (excluding the domain itself and its
+/// `www.` variant). User-defined entries extend the built-in list.
+fn family_of(domain: &str, custom: &BTreeMap<String, Vec<String>>) -> Vec<String> {
+    let root = domain.strip_prefix("www.").unwrap_or(domain);
+
+    let builtin = BUILTIN_FAMILIES
+        .iter()
+        .find(|(key, _)| *key == root)
+        .map(|(_, members)| members.iter().map(|m| m.to_string()))
+        .into_iter()
+        .flatten();
+
+    let user = custom
+        .get(root)
+        .or_else(|| custom.get(domain))
+        .into_iter()
+        .flatten()
+        .map(|m| m.to_lowercase());
+
+    builtin.chain(user).collect()
+}
+
+/// Expand blocked domains into every hostname the hosts block must cover.
+/// Order is stable (each domain followed by its family) and duplicates are
+/// dropped.
+pub fn expand(domains: &[String], custom: &BTreeMap<String, Vec<String>>) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    let mut push = |host: String| {
+        if !host.is_empty() && !out.contains(&host) {
+            out.push(host);
+        }
+    };
+
+    for domain in domains {
+        push(domain.clone());
+        // Automatically cover the www subdomain unless the entry already is www.
+        if !domain.starts_with("www.") {
+            push(format!("www.{domain}"));
+        }
+        for member in family_of(domain, custom) {
+            push(member);
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_domain_gets_www_only() {
+        let hosts = expand(&["example.org".into()], &BTreeMap::new());
+        assert_eq!(hosts, ["example.org", "www.example.org"]);
+    }
+
+    #[test]
+    fn builtin_family_is_expanded() {
+        let hosts = expand(&["youtube.com".into()], &BTreeMap::new());
+        assert_eq!(&hosts[..2], ["youtube.com", "www.youtube.com"]);
+        for h in [
+            "m.youtube.com",
+            "music.youtube.com",
+            "youtu.be",
+            "i.ytimg.com",
+        ] {
+            assert!(hosts.iter().any(|x| x == h), "missing {h}");
+        }
+    }
+
+    #[test]
+    fn www_entry_uses_root_family() {
+        let hosts = expand(&["www.reddit.com".into()], &BTreeMap::new());
+        assert_eq!(hosts[0], "www.reddit.com");
+        assert!(hosts.iter().any(|x| x == "old.reddit.com"));
+        assert!(!hosts.iter().any(|x| x == "www.www.reddit.com"));
+    }
+
+    #[test]
+    fn custom_family_extends_builtin_and_dedups() {
+        let mut custom = BTreeMap::new();
+        custom.insert(
+            "youtube.com".to_string(),
+            vec!["TV.youtube.com".to_string(), "m.youtube.com".to_string()],
+        );
+        let hosts = expand(&["youtube.com".into(), "youtu.be".into()], &custom);
+        assert!(hosts.iter().any(|x| x == "tv.youtube.com"));
+        assert_eq!(hosts.iter().filter(|x| *x == "m.youtube.com").count(), 1);
+        assert_eq!(hosts.iter().filter(|x| *x == "youtu.be").count(), 1);
+    }
+}
@@ -0,0 +1,576 @@
+//! Session history and statistics.
+//!
+//! Every session that ends — by its timer, by the user, or by being
+//! replaced — is appended to `history.log` (one JSON object per line) under
+//! `platform::config_dir()`. The file is never rewritten; `GET_STATS` and
+//! the `stats` command aggregate it on demand, and `history export` turns
+//! it into CSV, JSON, or the website's `session_stats` row.
+
+use crate::config::{Mode, SessionState};
+use crate::platform;
+use crate::schedule;
+use crate::AppError;
+use chrono::{DateTime, Local, NaiveDate, SecondsFormat, TimeDelta, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+/// A session that ended more than this long after its `end_time` expired
+/// while nothing was running to end it.
+const OFFLINE_GRACE_MS: u64 = 60 * 1000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EndReason {
+    /// The timer ran out.
+    Natural,
+    /// The timer ran out while neither the browser nor `restore` was
+    /// running; the session was closed when the host next saw it.
+    ExpiredOffline,
+    /// Ended early by the user.
+    Early,
+    /// Ended early with the PIN of a locked session.
+    Pin,
+    /// Overwritten by a new `START_SESSION`. Only in records written
+    /// before a running session had to be ended first.
+    Replaced,
+}
+
+impl EndReason {
+    /// `Natural`, or `ExpiredOffline` if `now` is well past `end_time`.
+    pub fn expired(end_time: Option<u64>, now: u64) -> Self {
+        match end_time {
+            Some(end) if now > end + OFFLINE_GRACE_MS => EndReason::ExpiredOffline,
+            _ => EndReason::Natural,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionRecord {
+    pub session_id: Option<String>,
+    pub mode: Mode,
+    pub start_time: u64,
+    pub planned_end: Option<u64>,
+    pub actual_end: u64,
+    pub end_reason: EndReason,
+    /// Time between start and actual end spent working: pauses and cycle
+    /// breaks don't count.
+    pub focus_ms: u64,
+    pub tamper_count: u32,
+    pub scheduled_id: Option<String>,
+}
+
+impl SessionRecord {
+    pub fn focus_minutes(&self) -> u64 {
+        self.focus_ms / 60_000
+    }
+}
+
+fn log_path() -> PathBuf {
+    platform::config_dir().join("history.log")
+}
+
+/// Append an ended session to the history. Inactive sessions and sessions
+/// without a start time are ignored.
+pub fn record(session: &SessionState, reason: EndReason, now: u64) -> Result<(), AppError> {
+    let Some(start_time) = session.start_time.filter(|_| session.mode.is_active()) else {
+        return Ok(());
+    };
+
+    // Ending while paused: the current pause doesn't count either.
+    let paused = session.paused_ms + session.paused_at.map_or(0, |at| now.saturating_sub(at));
+    let actual_end = match reason {
+        // Offline expiry: the session really ended at its planned end.
+        EndReason::ExpiredOffline => session.end_time.unwrap_or(now).min(now),
+        _ => now,
+    };
+    // A paused session has no `end_time`; it would have ended this much
+    // after the pause began.
+    let planned_end = session.end_time.or_else(|| {
+        session
+            .paused_at
+            .zip(session.paused_remaining_ms)
+            .map(|(at, remaining)| at + remaining)
+    });
+    let breaks = session
+        .cycle
+        .as_ref()
+        .map_or(0, |c| c.break_ms_at(actual_end, session.paused_at));
+    let record = SessionRecord {
+        session_id: session.id.clone(),
+        mode: session.mode,
+        start_time,
+        planned_end,
+        actual_end,
+        end_reason: reason,
+        focus_ms: actual_end
+            .saturating_sub(start_time)
+            .saturating_sub(paused)
+            .saturating_sub(breaks),
+        tamper_count: session.tamper_count,
+        scheduled_id: session.scheduled_id.clone(),
+    };
+
+    fs::create_dir_all(platform::config_dir())?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path())?;
+    writeln!(file, "{}", serde_json::to_string(&record)?)?;
+    Ok(())
+}
+
+/// Read the whole history, oldest first. Unreadable lines are skipped.
+pub fn load() -> Result<Vec<SessionRecord>, AppError> {
+    let file = match File::open(log_path()) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut records = Vec::new();
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(e) => eprintln!("[FocusBlocker] Skipping history line {}: {e}", n + 1),
+        }
+    }
+    Ok(records)
+}
+
+// =========================================================================
+// Statistics
+// =========================================================================
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Stats {
+    /// Oldest first, ending today.
+    pub days: Vec<Period>,
+    /// Weeks starting on Monday, oldest first, ending with this week.
+    pub weeks: Vec<Period>,
+    /// Consecutive days with focus time, up to today (or yesterday, if
+    /// nothing has been done yet today).
+    pub current_streak: u32,
+    pub longest_streak: u32,
+    pub total_sessions: u32,
+    pub total_focus_minutes: u64,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Period {
+    /// First day of the period.
+    pub start: NaiveDate,
+    pub sessions: u32,
+    pub focus_minutes: u64,
+}
+
+/// Aggregate `records` over the last `days` days and `weeks` weeks, in the
+/// system timezone.
+pub fn stats(records: &[SessionRecord], days: u32, weeks: u32) -> Stats {
+    stats_at(records, &Local::now(), days, weeks)
+}
+
+fn stats_at<Tz: TimeZone>(
+    records: &[SessionRecord],
+    now: &DateTime<Tz>,
+    days: u32,
+    weeks: u32,
+) -> Stats {
+    let tz = now.timezone();
+    let today = now.date_naive();
+
+    // Sessions count towards the local day they started on.
+    let mut by_day: BTreeMap<NaiveDate, Period> = BTreeMap::new();
+    for r in records {
+        let Some(date) = local_date(&tz, r.start_time) else {
+            continue;
+        };
+        let day = by_day.entry(date).or_insert_with(|| Period {
+            start: date,
+            ..Default::default()
+        });
+        day.sessions += 1;
+        day.focus_minutes += r.focus_minutes();
+    }
+
+    let period = |start: NaiveDate, len: i64| {
+        let end = start + TimeDelta::days(len);
+        by_day.range(start..end).fold(
+            Period {
+                start,
+                ..Default::default()
+            },
+            |mut acc, (_, day)| {
+                acc.sessions += day.sessions;
+                acc.focus_minutes += day.focus_minutes;
+                acc
+            },
+        )
+    };
+
+    let this_week = schedule::week_start(today);
+    let focused = |date: &NaiveDate| by_day.get(date).is_some_and(|d| d.focus_minutes > 0);
+
+    let mut current_streak = 0;
+    let mut date = if focused(&today) {
+        today
+    } else {
+        today - TimeDelta::days(1)
+    };
+    while focused(&date) {
+        current_streak += 1;
+        date -= TimeDelta::days(1);
+    }
+
+    let mut longest_streak = 0;
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for date in by_day.keys().filter(|d| focused(d)) {
+        run = match previous {
+            Some(p) if *date - p == TimeDelta::days(1) => run + 1,
+            _ => 1,
+        };
+        longest_streak = longest_streak.max(run);
+        previous = Some(*date);
+    }
+
+    Stats {
+        days: (0..days as i64)
+            .rev()
+            .map(|n| period(today - TimeDelta::days(n), 1))
+            .collect(),
+        weeks: (0..weeks as i64)
+            .rev()
+            .map(|n| period(this_week - TimeDelta::weeks(n), 7))
+            .collect(),
+        current_streak,
+        longest_streak,
+        total_sessions: records.len() as u32,
+        total_focus_minutes: records.iter().map(SessionRecord::focus_minutes).sum(),
+    }
+}
+
+/// Records whose session started between `from` and `to` (inclusive, local
+/// dates). Either bound may be left open.
+pub fn between(
+    records: Vec<SessionRecord>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Vec<SessionRecord> {
+    records
+        .into_iter()
+        .filter(|r| {
+            local_date(&Local, r.start_time)
+                .is_some_and(|date| from.is_none_or(|f| date >= f) && to.is_none_or(|t| date <= t))
+        })
+        .collect()
+}
+
+// =========================================================================
+// Export
+// =========================================================================
+
+/// One CSV line per session; times are local RFC 3339.
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    session_id: &'a str,
+    mode: Mode,
+    start_time: String,
+    planned_end: String,
+    actual_end: String,
+    end_reason: EndReason,
+    focus_minutes: u64,
+    tamper_count: u32,
+    scheduled_id: &'a str,
+}
+
+pub fn write_csv<W: Write>(records: &[SessionRecord], out: W) -> Result<(), AppError> {
+    let time = |ms: u64| {
+        Local
+            .timestamp_millis_opt(ms as i64)
+            .single()
+            .map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, false))
+            .unwrap_or_default()
+    };
+
+    let mut writer = csv::Writer::from_writer(out);
+    for r in records {
+        writer
+            .serialize(CsvRow {
+                session_id: r.session_id.as_deref().unwrap_or_default(),
+                mode: r.mode,
+                start_time: time(r.start_time),
+                planned_end: r.planned_end.map(time).unwrap_or_default(),
+                actual_end: time(r.actual_end),
+                end_reason: r.end_reason,
+                focus_minutes: r.focus_minutes(),
+                tamper_count: r.tamper_count,
+                scheduled_id: r.scheduled_id.as_deref().unwrap_or_default(),
+            })
+            .map_err(io::Error::from)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// The columns of the website's `session_stats` table, minus the keys it
+/// fills in itself (`user_id`, `updated_at`).
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct SessionStatsRow {
+    /// Sessions on `last_session_date`, like the extension's daily count.
+    pub sessions_today: u32,
+    pub total_sessions: u32,
+    pub total_focus_minutes: u64,
+    pub last_session_date: Option<NaiveDate>,
+}
+
+pub fn session_stats(records: &[SessionRecord]) -> SessionStatsRow {
+    session_stats_in(records, &Local)
+}
+
+fn session_stats_in<Tz: TimeZone>(records: &[SessionRecord], tz: &Tz) -> SessionStatsRow {
+    let dates: Vec<NaiveDate> = records
+        .iter()
+        .filter_map(|r| local_date(tz, r.start_time))
+        .collect();
+    let last_session_date = dates.iter().max().copied();
+
+    SessionStatsRow {
+        sessions_today: dates
+            .iter()
+            .filter(|d| Some(**d) == last_session_date)
+            .count() as u32,
+        total_sessions: records.len() as u32,
+        total_focus_minutes: records.iter().map(SessionRecord::focus_minutes).sum(),
+        last_session_date,
+    }
+}
+
+pub fn write_session_stats_csv<W: Write>(row: &SessionStatsRow, out: W) -> Result<(), AppError> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.serialize(row).map_err(io::Error::from)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Milliseconds from `now` to the next local midnight after `today`, when
+/// the daily session count rolls over.
+pub fn ms_until_tomorrow(today: NaiveDate, now: u64) -> u64 {
+    ms_until_next_day(&Local, today, now)
+}
+
+fn ms_until_next_day<Tz: TimeZone>(tz: &Tz, today: NaiveDate, now: u64) -> u64 {
+    let Some(midnight) = today.succ_opt().and_then(|d| d.and_hms_opt(0, 0, 0)) else {
+        return 0;
+    };
+    // Where DST starts at midnight, the day begins at 01:00 instead.
+    [midnight, midnight + TimeDelta::hours(1)]
+        .iter()
+        .find_map(|t| tz.from_local_datetime(t).earliest())
+        .map_or(0, |t| (t.timestamp_millis() as u64).saturating_sub(now))
+}
+
+/// The date `ms` falls on in `tz`.
+fn local_date<Tz: TimeZone>(tz: &Tz, ms: u64) -> Option<NaiveDate> {
+    tz.timestamp_millis_opt(ms as i64)
+        .single()
+        .map(|t| t.date_naive())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::sandbox::Sandbox;
+    use chrono_tz::America::New_York;
+
+    fn ms(y: i32, mo: u32, d: u32, h: u32) -> u64 {
+        New_York
+            .with_ymd_and_hms(y, mo, d, h, 0, 0)
+            .single()
+            .unwrap()
+            .timestamp_millis() as u64
+    }
+
+    fn record(start: u64, minutes: u64) -> SessionRecord {
+        SessionRecord {
+            session_id: None,
+            mode: Mode::Strict,
+            start_time: start,
+            planned_end: Some(start + minutes * 60_000),
+            actual_end: start + minutes * 60_000,
+            end_reason: EndReason::Natural,
+            focus_ms: minutes * 60_000,
+            tamper_count: 0,
+            scheduled_id: None,
+        }
+    }
+
+    #[test]
+    fn aggregates_days_weeks_and_streaks() {
+        // Wed 2024-03-13 .. Fri 2024-03-15, a gap, then Mon 2024-03-18.
+        let records = [
+            record(ms(2024, 3, 13, 9), 25),
+            record(ms(2024, 3, 14, 9), 25),
+            record(ms(2024, 3, 14, 14), 50),
+            record(ms(2024, 3, 15, 9), 30),
+            record(ms(2024, 3, 18, 9), 45),
+        ];
+        let now = New_York.with_ymd_and_hms(2024, 3, 19, 8, 0, 0).unwrap();
+        let stats = stats_at(&records, &now, 3, 2);
+
+        let days: Vec<_> = stats.days.iter().map(|d| d.focus_minutes).collect();
+        assert_eq!(days, [0, 45, 0]);
+        let weeks: Vec<_> = stats
+            .weeks
+            .iter()
+            .map(|w| (w.sessions, w.focus_minutes))
+            .collect();
+        assert_eq!(weeks, [(4, 130), (1, 45)]);
+        assert_eq!(
+            stats.weeks[1].start,
+            NaiveDate::from_ymd_opt(2024, 3, 18).unwrap()
+        );
+
+        // Nothing yet today, so the streak runs through yesterday.
+        assert_eq!(stats.current_streak, 1);
+        assert_eq!(stats.longest_streak, 3);
+        assert_eq!(stats.total_sessions, 5);
+        assert_eq!(stats.total_focus_minutes, 175);
+    }
+
+    #[test]
+    fn session_stats_counts_the_last_day() {
+        let records = [
+            record(ms(2024, 3, 14, 9), 25),
+            record(ms(2024, 3, 15, 9), 30),
+            // 23:00 in New York is already the 16th in UTC.
+            record(ms(2024, 3, 15, 23), 50),
+        ];
+        let row = session_stats_in(&records, &New_York);
+
+        assert_eq!(
+            row,
+            SessionStatsRow {
+                sessions_today: 2,
+                total_sessions: 3,
+                total_focus_minutes: 105,
+                last_session_date: NaiveDate::from_ymd_opt(2024, 3, 15),
+            }
+        );
+
+        let mut csv = Vec::new();
+        write_session_stats_csv(&row, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "sessions_today,total_sessions,total_focus_minutes,last_session_date\n2,3,105,2024-03-15\n"
+        );
+    }
+
+    const MIN: u64 = 60_000;
+
+    /// 25/5 cycle started at 0 and planned to end at 60 minutes.
+    fn cycling() -> SessionState {
+        SessionState {
+            mode: Mode::Strict,
+            start_time: Some(0),
+            end_time: Some(60 * MIN),
+            cycle: Some(crate::config::Cycle::new(25, 5, 0, 0, 0)),
+            ..Default::default()
+        }
+    }
+
+    fn focus_minutes(session: &SessionState, reason: EndReason, now: u64) -> u64 {
+        super::record(session, reason, now).unwrap();
+        load().unwrap().pop().unwrap().focus_minutes()
+    }
+
+    #[test]
+    fn cycle_breaks_are_not_focus() {
+        let _sandbox = Sandbox::new();
+        // Work 0-25, break 25-30, work 30-55, break 55-60.
+        assert_eq!(focus_minutes(&cycling(), EndReason::Natural, 60 * MIN), 50);
+        // Ended early, halfway through the second work phase.
+        assert_eq!(focus_minutes(&cycling(), EndReason::Early, 40 * MIN), 35);
+    }
+
+    #[test]
+    fn pauses_during_breaks_count_once() {
+        let _sandbox = Sandbox::new();
+        let mut s = cycling();
+        s.pause(27 * MIN);
+        // Ended while paused: 25 minutes of work, 2 of break, 13 paused.
+        assert_eq!(focus_minutes(&s, EndReason::Early, 40 * MIN), 25);
+
+        // Resumed: the break runs 25-40, work 40-65, break 65-70.
+        s.resume(37 * MIN);
+        assert_eq!(s.end_time, Some(70 * MIN));
+        assert_eq!(focus_minutes(&s, EndReason::Natural, 70 * MIN), 50);
+    }
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn daily_count_rolls_over_by_date() {
+        let mut count = crate::config::DailyCount::default();
+        let today = date(2024, 3, 15);
+        assert_eq!(count.on(today), 0);
+
+        count.increment(today);
+        count.increment(today);
+        assert_eq!(count.on(today), 2);
+
+        // Nothing resets the count; yesterday's simply doesn't apply.
+        let tomorrow = date(2024, 3, 16);
+        assert_eq!(count.on(tomorrow), 0);
+        count.increment(tomorrow);
+        assert_eq!(count.on(tomorrow), 1);
+        assert_eq!(count.on(today), 0);
+    }
+
+    #[test]
+    fn next_day_starts_at_local_midnight() {
+        let hour = 3_600_000;
+        let until = |today, now| ms_until_next_day(&New_York, today, now);
+
+        assert_eq!(until(date(2024, 3, 15), ms(2024, 3, 15, 22)), 2 * hour);
+        // Spring forward and fall back: 23- and 25-hour days.
+        assert_eq!(until(date(2024, 3, 10), ms(2024, 3, 10, 0)), 23 * hour);
+        assert_eq!(until(date(2024, 11, 3), ms(2024, 11, 3, 0)), 25 * hour);
+        // Already past midnight (clock skew): no wait.
+        assert_eq!(until(date(2024, 3, 15), ms(2024, 3, 16, 1)), 0);
+    }
+
+    #[test]
+    fn next_day_without_a_midnight_starts_at_one() {
+        use chrono_tz::America::Santiago;
+
+        // Chile skips from 2024-09-07 24:00 straight to 01:00.
+        let today = date(2024, 9, 7);
+        let now = Santiago
+            .with_ymd_and_hms(2024, 9, 7, 23, 0, 0)
+            .unwrap()
+            .timestamp_millis() as u64;
+        assert_eq!(ms_until_next_day(&Santiago, today, now), 3_600_000);
+    }
+
+    #[test]
+    fn late_expiry_is_offline() {
+        assert_eq!(EndReason::expired(Some(1_000), 30_000), EndReason::Natural);
+        assert_eq!(
+            EndReason::expired(Some(1_000), 1_000 + OFFLINE_GRACE_MS + 1),
+            EndReason::ExpiredOffline
+        );
+    }
+}
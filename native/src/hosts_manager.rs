@@ -1,28 +1,146 @@
 //! Safe hosts-file manipulation with marker-delimited blocks.
 //!
-//! All entries managed by this app live between two marker comments.
-//! The rest of the file is never touched.
+//! All entries managed by this app live between two marker comments, and
+//! nothing outside them is rewritten. The block is kept at the top of the
+//! file, since resolvers use the first line that matches a host. That
+//! isn't enough on its own: with glibc's `multi on` (the Debian and Ubuntu
+//! default) a later line still adds its address, so foreign lines that
+//! re-map a blocked host are reported wherever they are. The file is
+//! replaced atomically, never written in place.
+//!
+//! The expected block is always derived from the config, so changing the
+//! config and writing the matching block is done under `lock()`; the
+//! watchdog takes the same lock before comparing the two.
 
-use crate::platform;
+use crate::platform::{self, DnsFlush};
 use crate::AppError;
-use std::fs;
+use fs2::FileExt;
+use serde::Serialize;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::net::IpAddr;
 
 const MARKER_START: &str = "# FocusBlocker Start";
 const MARKER_END: &str = "# FocusBlocker End";
 
-/// Build the marker-delimited block for the given domains.
-fn build_block(domains: &[String]) -> String {
-    if domains.is_empty() {
+/// Prefix older versions put on foreign lines that overrode a block. Such
+/// lines are restored on the next write.
+const LEGACY_DISABLED_PREFIX: &str = "# FocusBlocker disabled: ";
+
+/// Everything needed to render the FocusBlocker section: which hostnames to
+/// block and which addresses to point them at (one line per address, so
+/// both IPv4 and IPv6 lookups are sinkholed).
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Blocklist {
+    /// Fully expanded hostnames — see `domain_families::expand`.
+    pub domains: Vec<String>,
+    pub sinks: Vec<IpAddr>,
+}
+
+impl Blocklist {
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    /// Every `(address, hostname)` pair the block maps.
+    fn entries(&self) -> Vec<(IpAddr, &str)> {
+        self.domains
+            .iter()
+            .flat_map(|host| self.sinks.iter().map(move |sink| (*sink, host.as_str())))
+            .collect()
+    }
+
+    fn blocks_host(&self, host: &str) -> bool {
+        self.domains.iter().any(|d| d.eq_ignore_ascii_case(host))
+    }
+
+    /// The blocked host a foreign hosts line maps to a non-sink address, if any.
+    fn overridden_by<'a>(&self, line: &'a str) -> Option<&'a str> {
+        let (addr, hosts) = parse_entry(line)?;
+        if self.sinks.contains(&addr) {
+            return None;
+        }
+        hosts.into_iter().find(|h| self.blocks_host(h))
+    }
+}
+
+/// A discrepancy between the hosts file and the expected block.
+/// Line numbers are 1-based.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Tamper {
+    /// No FocusBlocker section at all.
+    MissingSection,
+    /// Start marker without a matching end marker.
+    UnterminatedSection { line: usize },
+    /// A second start or end marker outside our section.
+    StrayMarker { line: usize, found: String },
+    /// A line inside the section differs from what `build_block` produces.
+    Modified {
+        line: usize,
+        expected: String,
+        found: String,
+    },
+    /// The section has more lines than expected.
+    Unexpected { line: usize, found: String },
+    /// The section is missing an expected line.
+    Missing { expected: String },
+    /// A line outside the section re-maps a blocked host to a real address.
+    /// Above the section it wins over the block until the block is moved
+    /// back up; below it, `multi on` resolvers still return it. The line
+    /// itself is left for the user to remove.
+    Override {
+        line: usize,
+        host: String,
+        found: String,
+    },
+}
+
+impl fmt::Display for Tamper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tamper::MissingSection => write!(f, "FocusBlocker section missing"),
+            Tamper::UnterminatedSection { line } => {
+                write!(f, "line {line}: section start marker has no end marker")
+            }
+            Tamper::StrayMarker { line, found } => write!(f, "line {line}: stray marker `{found}`"),
+            Tamper::Modified {
+                line,
+                expected,
+                found,
+            } => write!(f, "line {line}: expected `{expected}`, found `{found}`"),
+            Tamper::Unexpected { line, found } => write!(f, "line {line}: unexpected `{found}`"),
+            Tamper::Missing { expected } => write!(f, "missing `{expected}`"),
+            Tamper::Override { line, host, found } => {
+                write!(f, "line {line}: `{found}` overrides blocked host {host}")
+            }
+        }
+    }
+}
+
+/// Parse an `address host [host...]` line, ignoring trailing comments.
+/// Returns `None` for blanks, comments and malformed lines.
+fn parse_entry(line: &str) -> Option<(IpAddr, Vec<&str>)> {
+    let content = line.split('#').next()?;
+    let mut fields = content.split_whitespace();
+    let addr = fields.next()?.parse().ok()?;
+    let hosts: Vec<&str> = fields.collect();
+    if hosts.is_empty() {
+        return None;
+    }
+    Some((addr, hosts))
+}
+
+/// Build the marker-delimited block for the given blocklist.
+fn build_block(blocklist: &Blocklist) -> String {
+    if blocklist.is_empty() {
         return String::new();
     }
 
     let mut block = format!("{MARKER_START}\n");
-    for domain in domains {
-        block.push_str(&format!("127.0.0.1 {domain}\n"));
-        // Automatically cover the www subdomain unless the entry already is www.
-        if !domain.starts_with("www.") {
-            block.push_str(&format!("127.0.0.1 www.{domain}\n"));
-        }
+    for (sink, host) in blocklist.entries() {
+        block.push_str(&format!("{sink} {host}\n"));
     }
     block.push_str(MARKER_END);
     block
@@ -51,63 +169,334 @@ fn strip_block(content: &str) -> String {
     out
 }
 
-/// Write the canonical block for `domains` into the hosts file,
-/// replacing any existing FocusBlocker section. All other entries are preserved.
-pub fn apply(domains: &[String]) -> Result<(), AppError> {
+/// Produce the new hosts-file content for `blocklist`: the canonical block
+/// first, then the rest of the file as it was.
+fn render(content: &str, blocklist: &Blocklist) -> String {
+    let mut out = build_block(blocklist);
+    if !out.is_empty() {
+        out.push('\n');
+    }
+
+    for line in strip_block(content).lines() {
+        out.push_str(line.strip_prefix(LEGACY_DISABLED_PREFIX).unwrap_or(line));
+        out.push('\n');
+    }
+    out
+}
+
+/// Compare the hosts file against the block `build_block` would produce.
+/// Returns every discrepancy found; empty means intact.
+fn check(content: &str, blocklist: &Blocklist) -> Vec<Tamper> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut tampered = Vec::new();
+
+    let start = lines.iter().position(|l| l.trim() == MARKER_START);
+    let end = start.and_then(|s| {
+        lines[s + 1..]
+            .iter()
+            .position(|l| l.trim() == MARKER_END)
+            .map(|i| s + 1 + i)
+    });
+
+    let section = match (start, end) {
+        (Some(s), Some(e)) => Some((s, e)),
+        (Some(s), None) => {
+            tampered.push(Tamper::UnterminatedSection { line: s + 1 });
+            None
+        }
+        _ => {
+            tampered.push(Tamper::MissingSection);
+            None
+        }
+    };
+
+    // Section body, line for line.
+    if let Some((s, e)) = section {
+        let body = &lines[s + 1..e];
+        let expected: Vec<String> = blocklist
+            .entries()
+            .iter()
+            .map(|(sink, host)| format!("{sink} {host}"))
+            .collect();
+
+        for i in 0..body.len().max(expected.len()) {
+            let line = s + 2 + i;
+            match (body.get(i), expected.get(i)) {
+                (Some(found), Some(want)) if found.trim() == want => {}
+                (Some(found), Some(want)) => tampered.push(Tamper::Modified {
+                    line,
+                    expected: want.clone(),
+                    found: found.to_string(),
+                }),
+                (Some(found), None) => tampered.push(Tamper::Unexpected {
+                    line,
+                    found: found.to_string(),
+                }),
+                (None, Some(want)) => tampered.push(Tamper::Missing {
+                    expected: want.clone(),
+                }),
+                (None, None) => {}
+            }
+        }
+    }
+
+    // Everything outside the section: stray markers and overrides.
+    for (i, line) in lines.iter().enumerate() {
+        if section.is_some_and(|(s, e)| i >= s && i <= e) || start == Some(i) {
+            continue;
+        }
+        if line.trim() == MARKER_START || line.trim() == MARKER_END {
+            tampered.push(Tamper::StrayMarker {
+                line: i + 1,
+                found: line.to_string(),
+            });
+        } else if let Some(host) = blocklist.overridden_by(line) {
+            tampered.push(Tamper::Override {
+                line: i + 1,
+                host: host.to_string(),
+                found: line.to_string(),
+            });
+        }
+    }
+
+    tampered
+}
+
+fn read_hosts() -> Result<String, AppError> {
     let path = platform::hosts_file_path();
+    fs::read_to_string(&path)
+        .map_err(|e| AppError::Hosts(format!("Cannot read {}: {e}", path.display())))
+}
 
-    let content = fs::read_to_string(&path).map_err(|e| {
-        AppError::Hosts(format!("Cannot read {}: {e}", path.display()))
-    })?;
+/// Exclusive across threads and processes until dropped. See `lock`.
+pub struct HostsLock(File);
 
-    let mut cleaned = strip_block(&content);
+impl Drop for HostsLock {
+    fn drop(&mut self) {
+        self.0.unlock().ok();
+    }
+}
+
+/// Wait for the hosts lock. Whoever changes the config in a way that
+/// changes the blocklist holds it until the new block is written, so a
+/// holder always sees the hosts file caught up with the config. Not
+/// reentrant: take it once, at the outermost level.
+pub fn lock() -> Result<HostsLock, AppError> {
+    fs::create_dir_all(platform::config_dir())?;
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(platform::config_dir().join("hosts.lock"))?;
+    file.lock_exclusive()
+        .map_err(|e| AppError::Hosts(format!("Hosts lock failed: {e}")))?;
+    Ok(HostsLock(file))
+}
 
-    // Ensure a trailing newline before appending our block.
-    if !cleaned.ends_with('\n') {
-        cleaned.push('\n');
+/// Write the canonical block for `blocklist` into the hosts file,
+/// replacing any existing FocusBlocker section. All other entries are
+/// preserved. Returns the outcome of flushing each DNS cache afterwards,
+/// which is none if the file already had exactly this content.
+pub fn apply(blocklist: &Blocklist) -> Result<Vec<DnsFlush>, AppError> {
+    let path = platform::hosts_file_path();
+    let current = read_hosts()?;
+    let new_content = render(&current, blocklist);
+    if new_content == current {
+        return Ok(Vec::new());
     }
 
-    let block = build_block(domains);
-    let new_content = if block.is_empty() {
-        cleaned
-    } else {
-        format!("{cleaned}{block}\n")
+    // Through a symlinked hosts file to the real one, so the rename
+    // doesn't replace the link.
+    let path = fs::canonicalize(&path).unwrap_or(path);
+    let written = match platform::write_atomic(&path, &new_content) {
+        // A bind-mounted hosts file (containers) can't be renamed over.
+        Err(e) if e.kind() == io::ErrorKind::ResourceBusy => fs::write(&path, &new_content),
+        other => other,
     };
-
-    fs::write(&path, new_content).map_err(|e| {
+    written.map_err(|e| {
         AppError::Hosts(format!(
             "Cannot write {}: {e} (running as admin/root?)",
             path.display()
         ))
     })?;
 
-    platform::flush_dns();
-    Ok(())
+    Ok(platform::flush_dns())
 }
 
-/// Verify that every expected domain is present in the hosts file.
-/// If anything is missing (e.g. user or another tool removed entries),
-/// rewrite the entire block.
-pub fn ensure_integrity(domains: &[String]) -> Result<(), AppError> {
-    if domains.is_empty() {
-        return Ok(());
+/// Verify the hosts file against the expected block. If anything was
+/// tampered with (entries removed, edited or commented out, overrides
+/// added elsewhere), rewrite it and return what was found.
+pub fn ensure_integrity(blocklist: &Blocklist) -> Result<Vec<Tamper>, AppError> {
+    if blocklist.is_empty() {
+        return Ok(Vec::new());
     }
 
-    let path = platform::hosts_file_path();
-    let content = fs::read_to_string(&path).map_err(|e| {
-        AppError::Hosts(format!("Cannot read {}: {e}", path.display()))
-    })?;
+    let tampered = check(&read_hosts()?, blocklist);
+    if !tampered.is_empty() {
+        apply(blocklist)?;
+    }
 
-    // Quick check: markers must exist and every domain must appear.
-    let intact = content.contains(MARKER_START)
-        && content.contains(MARKER_END)
-        && domains
-            .iter()
-            .all(|d| content.contains(&format!("127.0.0.1 {d}")));
+    Ok(tampered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn youtube() -> Blocklist {
+        Blocklist {
+            domains: vec!["youtube.com".into(), "www.youtube.com".into()],
+            sinks: vec![
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ],
+        }
+    }
 
-    if !intact {
-        apply(domains)?;
+    const BASE: &str = "127.0.0.1 localhost\n::1 localhost\n";
+
+    #[test]
+    fn rendered_file_is_intact() {
+        let content = render(BASE, &youtube());
+        assert!(content.starts_with(MARKER_START));
+        assert!(content.ends_with(BASE));
+        assert_eq!(check(&content, &youtube()), []);
+    }
+
+    #[test]
+    fn render_is_idempotent_and_clear_restores() {
+        let once = render(BASE, &youtube());
+        assert_eq!(render(&once, &youtube()), once);
+        assert_eq!(render(&once, &Blocklist::default()), BASE);
+    }
+
+    #[test]
+    fn missing_section_is_reported() {
+        assert_eq!(check(BASE, &youtube()), [Tamper::MissingSection]);
+    }
+
+    #[test]
+    fn commented_out_line_is_reported() {
+        let content =
+            render(BASE, &youtube()).replace("::1 www.youtube.com", "# ::1 www.youtube.com");
+        assert_eq!(
+            check(&content, &youtube()),
+            [Tamper::Modified {
+                line: 5,
+                expected: "::1 www.youtube.com".into(),
+                found: "# ::1 www.youtube.com".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn lookalike_domain_does_not_count() {
+        let content = render(BASE, &youtube())
+            .replace("127.0.0.1 youtube.com\n", "127.0.0.1 youtube.com.evil\n");
+        let tampered = check(&content, &youtube());
+        assert!(matches!(tampered[..], [Tamper::Modified { line: 2, .. }]));
+    }
+
+    #[test]
+    fn override_above_block_is_reported_and_outranked() {
+        let sneaky = "142.250.80.46 youtube.com # sneaky";
+        let content = format!("{sneaky}\n{}", render(BASE, &youtube()));
+        assert_eq!(
+            check(&content, &youtube()),
+            [Tamper::Override {
+                line: 1,
+                host: "youtube.com".into(),
+                found: sneaky.into(),
+            }]
+        );
+
+        // The block moves back on top; the foreign line is left as it is,
+        // and still reported.
+        let repaired = render(&content, &youtube());
+        assert_eq!(
+            check(&repaired, &youtube()),
+            [Tamper::Override {
+                line: 7,
+                host: "youtube.com".into(),
+                found: sneaky.into(),
+            }]
+        );
+        assert!(repaired.starts_with(MARKER_START));
+        assert!(repaired.contains(&format!("\n{sneaky}\n")));
+
+        let cleared = render(&repaired, &Blocklist::default());
+        assert_eq!(cleared, format!("{sneaky}\n{BASE}"));
+    }
+
+    #[test]
+    fn override_below_block_is_reported_and_left_alone() {
+        let content = format!(
+            "{}93.184.216.34 youtube.com\n0.0.0.0 example.com\n127.0.0.1 youtube.com\n",
+            render(BASE, &youtube())
+        );
+        // Line 9 maps to a sink, so it blocks rather than overrides.
+        assert_eq!(
+            check(&content, &youtube()),
+            [Tamper::Override {
+                line: 9,
+                host: "youtube.com".into(),
+                found: "93.184.216.34 youtube.com".into(),
+            }]
+        );
+        assert_eq!(render(&content, &youtube()), content);
+    }
+
+    #[test]
+    fn legacy_disabled_lines_are_restored() {
+        let content = format!("{BASE}{LEGACY_DISABLED_PREFIX}10.0.0.1 youtube.com\n");
+        let rendered = render(&content, &youtube());
+        assert!(rendered.ends_with(&format!("{BASE}10.0.0.1 youtube.com\n")));
+        assert!(matches!(
+            check(&rendered, &youtube())[..],
+            [Tamper::Override { line: 9, .. }]
+        ));
     }
 
-    Ok(())
+    #[test]
+    fn extra_line_and_stray_marker_are_reported() {
+        let content = render(BASE, &youtube())
+            .replace(MARKER_END, &format!("10.0.0.1 intranet\n{MARKER_END}"))
+            + MARKER_START
+            + "\n";
+        let tampered = check(&content, &youtube());
+        assert_eq!(
+            tampered,
+            [
+                Tamper::Unexpected {
+                    line: 6,
+                    found: "10.0.0.1 intranet".into(),
+                },
+                Tamper::StrayMarker {
+                    line: 10,
+                    found: MARKER_START.into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_replaces_the_hosts_file() {
+        let sandbox = crate::platform::sandbox::Sandbox::new();
+        let hosts = platform::hosts_file_path();
+        fs::write(&hosts, BASE).unwrap();
+
+        apply(&youtube()).unwrap();
+        assert_eq!(ensure_integrity(&youtube()).unwrap(), []);
+        apply(&Blocklist::default()).unwrap();
+
+        assert_eq!(fs::read_to_string(&hosts).unwrap(), BASE);
+        let leftovers: Vec<_> = fs::read_dir(&sandbox.root)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .filter(|n| n != "hosts")
+            .collect();
+        assert!(leftovers.is_empty(), "{leftovers:?}");
+    }
 }
@@ -5,28 +5,47 @@
 //! block rules, and settings — shared across all browser profiles and browsers.
 //!
 //! Mode-based state machine (v2):
-//!   session.mode: "off" | "precision" | "strict"
+//!   session.mode: `config::Mode` — "off" | "precision" | "strict"
 //!   - off:       no enforcement
 //!   - precision: extension-only channel blocking (no hosts changes)
 //!   - strict:    hosts-level domain blocking (youtube.com + blocked_domains)
 //!
 //! Usage:
-//!   focus-blocker-native          # Native messaging mode (launched by Chrome)
+//!   focus-blocker-native          # Native messaging mode (launched by the browser)
 //!   focus-blocker-native setup    # Interactive first-time password setup
 //!   focus-blocker-native restore  # Re-apply persisted blocks + monitor session expiry
+//!   focus-blocker-native status | start | stop | switch | block | unblock
+//!                        list | rules | settings | schedules | stats   # Operator CLI, see `--help`
+//!   focus-blocker-native --json <command>              # Print the GET_STATE document
 
+mod calendar;
+mod cli;
 mod config;
+mod domain_families;
+mod history;
 mod hosts_manager;
+mod migrations;
 mod native_messaging;
 mod password;
 mod platform;
+mod protocol;
+mod schedule;
+mod tamper;
 #[cfg(windows)]
 mod registry;
 mod watchdog;
 
-use serde_json::json;
-use std::io;
-use std::sync::{Arc, Mutex};
+use config::{EarlyEnd, Mode};
+use history::EndReason;
+use hosts_manager::Blocklist;
+use password::Credential;
+use protocol::{
+    CyclePayload, DailySessionsView, ErrorCode, ErrorReply, RecoveryView, Reply, Request, Response,
+    SchedulePayload, ScheduleView, SessionView, SettingsPayload, SettingsView, StateReply,
+    YoutubeRulesPayload, YoutubeRulesView,
+};
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal};
 use std::thread;
 use std::time::Duration;
 use thiserror::Error;
@@ -54,6 +73,29 @@ pub enum AppError {
 
     #[error("Messaging error: {0}")]
     Messaging(String),
+
+    #[error("Calendar error: {0}")]
+    Calendar(String),
+
+    /// A request refused by a message handler, surfaced to a CLI caller.
+    #[error("{}", .0.message)]
+    Rejected(ErrorReply),
+}
+
+impl AppError {
+    /// Machine-readable code for structured (`--json`) error output.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::Io(_) => ErrorCode::IoError,
+            AppError::Json(_) => ErrorCode::JsonError,
+            AppError::Config(_) => ErrorCode::ConfigError,
+            AppError::Password(_) => ErrorCode::PasswordError,
+            AppError::Hosts(_) => ErrorCode::HostsError,
+            AppError::Messaging(_) => ErrorCode::MessagingError,
+            AppError::Calendar(_) => ErrorCode::CalendarError,
+            AppError::Rejected(e) => e.code,
+        }
+    }
 }
 
 // =========================================================================
@@ -61,14 +103,20 @@ pub enum AppError {
 // =========================================================================
 
 fn main() {
-    let result = match std::env::args().nth(1).as_deref() {
-        Some("setup") => run_setup(),
-        Some("restore") => run_restore(),
-        _ => run_native_messaging(),
-    };
+    let args: Vec<String> = std::env::args().collect();
+
+    if cli::is_native_messaging_launch(&args) {
+        if let Err(e) = run_native_messaging() {
+            eprintln!("[FocusBlocker] Fatal: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    if let Err(e) = result {
-        eprintln!("[FocusBlocker] Fatal: {e}");
+    let cli = cli::parse(args);
+    let json = cli.json;
+    if let Err(e) = cli::run(cli) {
+        cli::report_error(&e, json);
         std::process::exit(1);
     }
 }
@@ -77,51 +125,137 @@ fn main() {
 // Setup mode (interactive CLI)
 // =========================================================================
 
-fn run_setup() -> Result<(), AppError> {
-    println!("Focus Blocker — Initial Setup");
-    println!("-----------------------------");
+/// Set the first admin password. Returns the new recovery codes for the
+/// caller to show, or `None` if a password was already set.
+fn run_setup(input: SecretInput) -> Result<Option<Vec<String>>, AppError> {
+    eprintln!("Focus Blocker — Initial Setup");
+    eprintln!("-----------------------------");
 
-    let mut cfg = config::load()?;
+    let cfg = config::load()?;
 
     if cfg.password_hash.is_some() {
-        println!("Password already configured.");
-        println!(
+        eprintln!("Password already configured.");
+        eprintln!(
             "Config: {}",
             platform::config_dir().join("config.json").display()
         );
-        println!("Delete the config file to reset.");
-        return Ok(());
+        eprintln!("Use `password change` to replace it, or");
+        eprintln!("`password reset` with a recovery code if it is forgotten.");
+        return Ok(None);
     }
 
-    // TODO: Use the rpassword crate for masked terminal input in production.
-    let pw = prompt("Create a password: ")?;
-    if pw.is_empty() {
-        return Err(AppError::Password("Password cannot be empty".into()));
+    let pw = input.read("Create an admin password: ")?;
+    if let Some(reason) = password::weakness(&pw, Credential::Admin) {
+        return Err(AppError::Password(reason));
     }
+    input.confirm(&pw, "Confirm password:  ")?;
 
-    let pw2 = prompt("Confirm password:  ")?;
-    if pw != pw2 {
-        return Err(AppError::Password("Passwords do not match".into()));
-    }
+    let policy = measure_hashing(&cfg.argon2)?;
 
-    cfg.password_hash = Some(password::hash(&pw)?);
-    config::save(&cfg)?;
+    let hash = password::hash_with(&pw, &policy)?;
+    let (codes, code_hashes) = password::recovery_codes(&policy)?;
+    // Only touch the credentials: rules and a running session stay put.
+    config::update(|cfg| {
+        cfg.password_hash = Some(hash);
+        cfg.recovery_codes = code_hashes;
+        cfg.argon2 = policy;
+    })?;
 
-    println!("Password set successfully.");
-    println!(
+    eprintln!("Password set successfully.");
+    eprintln!(
         "Config saved to: {}",
         platform::config_dir().join("config.json").display()
     );
+    eprintln!("Add a PIN for ending locked sessions with `pin set <label>`.");
+    eprintln!();
+    eprintln!("Reset a forgotten password with `password reset` and a recovery code.");
+    Ok(Some(codes))
+}
+
+/// Re-measure the Argon2 policy, for installs set up on other hardware or
+/// before calibration existed. Needs the admin password, if one is set,
+/// and rehashes it with the new policy.
+fn run_calibrate(input: SecretInput) -> Result<(), AppError> {
+    let cfg = config::load()?;
+    let pw = match cfg.password_hash {
+        Some(_) => input.read("Admin password: ")?,
+        None => String::new(),
+    };
+    if let Some(Response::Error(reply)) = check_admin(&pw)? {
+        return Err(AppError::Rejected(reply));
+    }
+
+    let policy = measure_hashing(&cfg.argon2)?;
+    // Re-read: `check_admin` may have just rehashed it.
+    let verified = config::load()?.password_hash;
+    let hash = match verified {
+        Some(_) => Some(password::hash_with(&pw, &policy)?),
+        None => None,
+    };
+    config::update(|cfg| {
+        cfg.argon2 = policy;
+        // Unless it was changed meanwhile.
+        if cfg.password_hash == verified {
+            cfg.password_hash = hash;
+        }
+    })?;
+    eprintln!("Hashing policy saved; session PINs are upgraded when next used.");
     Ok(())
 }
 
-fn prompt(label: &str) -> Result<String, AppError> {
-    use io::Write;
-    print!("{label}");
-    io::stdout().flush()?;
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-    Ok(input.trim().to_string())
+fn measure_hashing(current: &config::Argon2Policy) -> Result<config::Argon2Policy, AppError> {
+    eprintln!("Measuring hashing speed...");
+    let policy = password::calibrate(current)?;
+    eprintln!(
+        "Argon2id: {} MiB, {} passes (target {} ms per check).",
+        policy.memory_kib / 1024,
+        policy.iterations,
+        policy.target_ms
+    );
+    Ok(policy)
+}
+
+/// Where the CLI reads passwords and PINs from.
+#[derive(Debug, Clone, Copy)]
+pub enum SecretInput {
+    /// Prompt on the terminal without echo.
+    Terminal,
+    /// One line per secret from stdin (`--password-stdin`), no prompts
+    /// and no confirmation.
+    Stdin,
+}
+
+impl SecretInput {
+    pub fn read(self, label: &str) -> Result<String, AppError> {
+        match self {
+            SecretInput::Terminal => {
+                // Without this, a script piping a password would block on
+                // /dev/tty, or fail without saying why.
+                if !io::stdin().is_terminal() {
+                    return Err(AppError::Password(
+                        "stdin is not a terminal; pass --password-stdin to read secrets from it"
+                            .into(),
+                    ));
+                }
+                Ok(rpassword::prompt_password(label)?)
+            }
+            SecretInput::Stdin => {
+                let mut line = String::new();
+                if io::stdin().read_line(&mut line)? == 0 {
+                    return Err(AppError::Password("stdin ended before all secrets".into()));
+                }
+                Ok(line.trim_end_matches(['\r', '\n']).to_string())
+            }
+        }
+    }
+
+    /// On a terminal, ask for `secret` again and fail if it doesn't match.
+    pub fn confirm(self, secret: &str, label: &str) -> Result<(), AppError> {
+        if matches!(self, SecretInput::Terminal) && self.read(label)? != secret {
+            return Err(AppError::Password("Entries do not match".into()));
+        }
+        Ok(())
+    }
 }
 
 // =========================================================================
@@ -129,44 +263,52 @@ fn prompt(label: &str) -> Result<String, AppError> {
 // =========================================================================
 
 fn run_restore() -> Result<(), AppError> {
+    let hosts = hosts_manager::lock()?;
     let cfg = config::load()?;
 
-    let domains = collect_blocked_domains(&cfg);
-    let session_mode = cfg
-        .session
-        .as_ref()
-        .map_or("off", |s| s.mode.as_str())
-        .to_string();
-
-    if domains.is_empty() && !config::is_mode_active(&session_mode) {
-        eprintln!("[FocusBlocker] Restore: no persisted blocks and no active session, exiting.");
+    let blocklist = collect_blocklist(&cfg);
+    if blocklist.is_empty() && !cfg.session_mode().is_active() && !cfg.has_enabled_schedules() {
+        eprintln!("[FocusBlocker] Restore: no persisted blocks, active session or schedules, exiting.");
         return Ok(());
     }
 
-    if !domains.is_empty() {
+    if !blocklist.is_empty() {
         eprintln!(
             "[FocusBlocker] Restore: re-applying {} domain(s).",
-            domains.len()
+            blocklist.domains.len()
         );
-        hosts_manager::apply(&domains)?;
+        hosts_manager::apply(&blocklist)?;
     }
+    drop(hosts);
 
     // Start watchdog to guard against tampering.
-    let blocked = Arc::new(Mutex::new(domains));
-    let _watchdog = watchdog::start(Arc::clone(&blocked));
+    let _watchdog = watchdog::start();
 
     // Poll config file every 10s. Check for:
-    // 1. Session expiry → auto-end and clean up hosts
-    // 2. Domain list changes → update watchdog state
-    // 3. All clear → exit
+    // 1. Schedule window opening → auto-start a session
+    // 2. Pause over the allowed length → auto-resume
+    // 3. Cycle phase over → switch between work and break
+    // 4. Session expiry → auto-end and clean up hosts
+    // 5. Blocklist changed by any of the above → apply it
+    // 6. All clear → exit
+    //
+    // Other processes apply their own changes, and repairing anything else
+    // is the watchdog's job, so hosts are only written for changes made here.
     loop {
         thread::sleep(Duration::from_secs(10));
 
+        let _hosts = hosts_manager::lock()?;
+        let before = collect_blocklist(&config::load()?);
+
+        start_due_schedule()?;
+        resume_overlong_pause()?;
+        advance_cycle()?;
+
         let current = config::load()?;
 
         // Check for session expiry
         if let Some(ref session) = current.session {
-            if config::is_mode_active(&session.mode) {
+            if session.mode.is_active() {
                 if let Some(end_time) = session.end_time {
                     if config::now_ms() >= end_time {
                         eprintln!("[FocusBlocker] Restore: session expired, auto-ending.");
@@ -177,25 +319,18 @@ fn run_restore() -> Result<(), AppError> {
             }
         }
 
-        let current_domains = collect_blocked_domains(&current);
-        let current_mode = current
-            .session
-            .as_ref()
-            .map_or("off", |s| s.mode.as_str())
-            .to_string();
-
-        if current_domains.is_empty() && !config::is_mode_active(&current_mode) {
+        let current_blocklist = collect_blocklist(&current);
+        if current_blocklist.is_empty()
+            && !current.session_mode().is_active()
+            && !current.has_enabled_schedules()
+        {
             eprintln!("[FocusBlocker] Restore: domains cleared and no active session, cleaning up.");
-            hosts_manager::apply(&[])?;
+            hosts_manager::apply(&Blocklist::default())?;
             break;
         }
 
-        // Sync in-memory state so watchdog uses the latest list.
-        if let Ok(mut guard) = blocked.lock() {
-            if *guard != current_domains {
-                hosts_manager::apply(&current_domains)?;
-                *guard = current_domains;
-            }
+        if current_blocklist != before {
+            hosts_manager::apply(&current_blocklist)?;
         }
     }
 
@@ -204,32 +339,138 @@ fn run_restore() -> Result<(), AppError> {
 
 /// Auto-end an expired session: clear session state and hosts file.
 fn auto_end_session() -> Result<(), AppError> {
+    let mut ended = None;
+    config::update(|cfg| {
+        ended = cfg.session.replace(config::SessionState::default());
+        cfg.blocked_domains.clear();
+    })?;
+    hosts_manager::apply(&Blocklist::default())?;
+
+    if let Some(session) = ended {
+        let now = config::now_ms();
+        history::record(&session, EndReason::expired(session.end_time, now), now)?;
+    }
+    Ok(())
+}
+
+/// Start a session for a schedule whose window has just opened, unless a
+/// session is already running. Hosts are applied by the caller's next sync.
+fn start_due_schedule() -> Result<(), AppError> {
+    let now = config::now_ms();
+
+    // Cheap pre-check so idle ticks don't rewrite the config.
+    let cfg = config::load()?;
+    if cfg.session_mode().is_active() || schedule::due(&cfg.schedules, now).is_none() {
+        return Ok(());
+    }
+
+    let mut started = None;
     config::update(|cfg| {
+        if cfg.session_mode().is_active() {
+            return;
+        }
+        let Some((due, window)) = schedule::due(&cfg.schedules, now) else {
+            return;
+        };
+        let (id, mode, locked) = (due.id.clone(), due.mode, due.locked);
+
         cfg.session = Some(config::SessionState {
-            mode: "off".to_string(),
-            start_time: None,
-            end_time: None,
-            locked: false,
-            scheduled_id: None,
+            mode,
+            start_time: Some(now),
+            end_time: Some(window.end),
+            locked,
+            scheduled_id: Some(id.clone()),
+            id: Some(config::new_session_id(now)),
             ..Default::default()
         });
-        cfg.blocked_domains.clear();
+        if let Some(s) = cfg.schedules.iter_mut().find(|s| s.id == id) {
+            s.last_run = Some(window.start);
+        }
+        started = Some((id, mode));
     })?;
-    hosts_manager::apply(&[])?;
+
+    if let Some((id, mode)) = started {
+        eprintln!("[FocusBlocker] Restore: schedule '{id}' opened, started {mode} session.");
+    }
     Ok(())
 }
 
-/// Build the full list of domains to block in the hosts file.
-/// In strict mode: blocked_domains + youtube.com.
-/// In precision/off: empty (no hosts enforcement).
-fn collect_blocked_domains(cfg: &config::Config) -> Vec<String> {
-    let session_mode = cfg
+/// Resume a session paused for longer than `PausePolicy.max_pause_minutes`.
+/// Hosts are applied by the caller's next sync. Returns true if the config
+/// changed.
+fn resume_overlong_pause() -> Result<bool, AppError> {
+    let now = config::now_ms();
+    let overlong = |cfg: &config::Config| {
+        let max = cfg
+            .global_settings
+            .as_ref()
+            .map(|gs| gs.pause_policy.clone())
+            .unwrap_or_default()
+            .max_pause_minutes;
+        let paused_at = cfg.session.as_ref().and_then(|s| s.paused_at);
+        matches!((max, paused_at), (Some(max), Some(at)) if now >= at + max as u64 * 60 * 1000)
+    };
+
+    if !overlong(&config::load()?) {
+        return Ok(false);
+    }
+
+    let mut resumed = false;
+    config::update(|cfg| {
+        if overlong(cfg) {
+            if let Some(ref mut session) = cfg.session {
+                session.resume(now);
+                resumed = true;
+            }
+        }
+    })?;
+
+    if resumed {
+        eprintln!("[FocusBlocker] Pause limit reached, session resumed.");
+    }
+    Ok(resumed)
+}
+
+/// Move a cycling session into its current work or break phase. Hosts are
+/// applied by the caller's next sync. Returns true if the config changed.
+fn advance_cycle() -> Result<bool, AppError> {
+    let now = config::now_ms();
+    let due = |cfg: &config::Config| {
+        cfg.session
+            .clone()
+            .is_some_and(|mut s| s.advance_cycle(now))
+    };
+
+    if !due(&config::load()?) {
+        return Ok(false);
+    }
+
+    let mut phase = None;
+    config::update(|cfg| {
+        if let Some(ref mut session) = cfg.session {
+            if session.advance_cycle(now) {
+                phase = session.cycle.as_ref().map(|c| c.phase);
+            }
+        }
+    })?;
+
+    if let Some(phase) = phase {
+        eprintln!("[FocusBlocker] Cycle: {phase} phase started.");
+    }
+    Ok(phase.is_some())
+}
+
+/// Build the full blocklist for the hosts file.
+/// In strict mode: blocked_domains + youtube.com, each expanded to its domain
+/// family and sunk to the configured addresses.
+/// In precision/off, or while paused: empty (no hosts enforcement).
+fn collect_blocklist(cfg: &config::Config) -> Blocklist {
+    let lifted = cfg
         .session
         .as_ref()
-        .map_or("off", |s| s.mode.as_str());
-
-    if session_mode != "strict" {
-        return vec![];
+        .is_some_and(|s| s.is_paused() || s.in_break());
+    if cfg.session_mode() != Mode::Strict || lifted {
+        return Blocklist::default();
     }
 
     let mut domains = cfg.blocked_domains.clone();
@@ -237,19 +478,30 @@ fn collect_blocked_domains(cfg: &config::Config) -> Vec<String> {
     if !domains.contains(&yt) {
         domains.push(yt);
     }
-    domains
+    Blocklist {
+        domains: domain_families::expand(&domains, &cfg.domain_families),
+        sinks: cfg.sink_addresses(),
+    }
 }
 
 // =========================================================================
 // Native messaging mode
 // =========================================================================
 
-fn run_native_messaging() -> Result<(), AppError> {
-    let cfg = config::load()?;
-    let blocked = Arc::new(Mutex::new(collect_blocked_domains(&cfg)));
+const TICK: Duration = Duration::from_secs(10);
 
+fn run_native_messaging() -> Result<(), AppError> {
     // Background thread: re-applies hosts entries if they're tampered with.
-    let _watchdog = watchdog::start(Arc::clone(&blocked));
+    let _watchdog = watchdog::start();
+
+    // Background thread: ends overlong pauses and moves cycling sessions
+    // between work and break even when the extension is quiet.
+    let _ticker = thread::spawn(|| loop {
+        thread::sleep(TICK);
+        if let Err(e) = tick() {
+            eprintln!("[FocusBlocker] Session update failed: {e}");
+        }
+    });
 
     let stdin = io::stdin();
     let stdout = io::stdout();
@@ -259,7 +511,10 @@ fn run_native_messaging() -> Result<(), AppError> {
     loop {
         match native_messaging::read_message(&mut reader) {
             Ok(msg) => {
-                let (response, quit) = handle_message(&msg, &blocked)?;
+                let (response, quit) = match Request::from_value(msg) {
+                    Ok(request) => handle_message(request)?,
+                    Err(e) => (Response::Error(e), false),
+                };
                 native_messaging::write_message(&mut writer, &response)?;
                 if quit {
                     break;
@@ -274,41 +529,113 @@ fn run_native_messaging() -> Result<(), AppError> {
     Ok(())
 }
 
+/// Resume an overlong pause and advance the cycle, then apply or lift the
+/// hosts block if either changed the session.
+fn tick() -> Result<(), AppError> {
+    let _hosts = hosts_manager::lock()?;
+    let resumed = resume_overlong_pause()?;
+    if advance_cycle()? || resumed {
+        hosts_manager::apply(&collect_blocklist(&config::load()?))?;
+    }
+    Ok(())
+}
+
 // =========================================================================
 // Message dispatch
 // =========================================================================
 
-fn handle_message(
-    msg: &serde_json::Value,
-    blocked: &Arc<Mutex<Vec<String>>>,
-) -> Result<(serde_json::Value, bool), AppError> {
-    let msg_type = msg["type"].as_str().unwrap_or("");
-
-    match msg_type {
-        "PING" => Ok((json!({"status": "OK"}), false)),
+/// Handle one request. The hosts lock is held throughout, so state changes
+/// and the hosts entries that follow from them land together.
+fn handle_message(request: Request) -> Result<(Response, bool), AppError> {
+    let _hosts = hosts_manager::lock()?;
+    match request {
+        Request::Ping {} => Ok((Response::ack(), false)),
 
         // ---- Cross-profile state sync ----
 
-        "GET_STATE" => handle_get_state(),
+        Request::GetState {} => handle_get_state(),
 
-        "START_SESSION" => handle_start_session(msg, blocked),
+        Request::DismissRecovery {} => {
+            config::update(|cfg| cfg.recovered = None)?;
+            Ok((Response::ack(), false))
+        }
 
-        "END_SESSION" => handle_end_session(msg, blocked),
+        Request::GetStats { days, weeks } => {
+            let days = days.unwrap_or(7).min(366);
+            let weeks = weeks.unwrap_or(4).min(53);
+            let stats = history::stats(&history::load()?, days, weeks);
+            Ok((Response::Ok(Reply::Stats { stats }), false))
+        }
 
-        "SWITCH_MODE" => handle_switch_mode(msg, blocked),
+        Request::GetHistory { from, to } => {
+            let sessions = history::between(history::load()?, from, to);
+            Ok((Response::Ok(Reply::History { sessions }), false))
+        }
 
-        "SYNC_RULES" => handle_sync_rules(msg),
+        Request::StartSession {
+            duration_minutes,
+            scheduled_id,
+            locked,
+            mode,
+            cycle,
+        } => handle_start_session(duration_minutes, scheduled_id, locked, mode, cycle),
+
+        Request::EndSession {
+            natural,
+            parent_pin,
+            confirmation,
+            password: pw,
+        } => handle_end_session(
+            natural,
+            parent_pin.as_deref().unwrap_or(""),
+            confirmation.as_deref().unwrap_or(""),
+            pw.as_deref().unwrap_or(""),
+        ),
+
+        Request::ExtendSession { minutes } => handle_extend_session(minutes),
+
+        Request::PauseSession { parent_pin } => {
+            handle_pause_session(parent_pin.as_deref().unwrap_or(""))
+        }
 
-        "SYNC_SETTINGS" => handle_sync_settings(msg),
+        Request::ResumeSession {} => handle_resume_session(),
+
+        Request::SwitchMode {
+            mode,
+            parent_pin,
+            confirmation,
+            password: pw,
+        } => handle_switch_mode(
+            mode,
+            parent_pin.as_deref().unwrap_or(""),
+            confirmation.as_deref().unwrap_or(""),
+            pw.as_deref().unwrap_or(""),
+        ),
+
+        Request::SyncRules {
+            youtube_rules,
+            blocked_sites,
+            domain_families,
+        } => handle_sync_rules(youtube_rules, blocked_sites, domain_families),
+
+        Request::SyncSettings {
+            settings,
+            password: pw,
+        } => handle_sync_settings(settings, pw.as_deref().unwrap_or("")),
+
+        Request::SyncSchedules { schedules } => handle_sync_schedules(schedules),
 
         // ---- Registry management (Windows) ----
 
-        "REGISTER_EXTENSION" => handle_register_extension(msg),
+        Request::RegisterExtension {
+            extension_id,
+            manifest_path,
+        } => handle_register_extension(&extension_id, manifest_path.as_deref().unwrap_or("")),
 
         // ---- Legacy per-domain controls (compat for old extension) ----
 
-        "BLOCK_DOMAIN" => {
-            let domain = require_field(msg, "domain")?;
+        Request::BlockDomain { domain } => {
+            let domain = domain.to_lowercase();
 
             let cfg = config::update(move |cfg| {
                 if !cfg.blocked_domains.contains(&domain) {
@@ -316,57 +643,61 @@ fn handle_message(
                 }
             })?;
 
-            let domains = collect_blocked_domains(&cfg);
-            if !domains.is_empty() {
-                hosts_manager::apply(&domains)?;
-            }
-
-            if let Ok(mut guard) = blocked.lock() {
-                *guard = domains;
-            }
+            hosts_manager::apply(&collect_blocklist(&cfg))?;
 
-            Ok((json!({"status": "OK"}), false))
+            Ok((Response::ack(), false))
         }
 
-        "UNBLOCK_DOMAIN" => {
-            let domain = require_field(msg, "domain")?;
+        Request::UnblockDomain { domain } => {
+            let domain = domain.to_lowercase();
 
             let cfg = config::update(move |cfg| {
                 cfg.blocked_domains.retain(|d| *d != domain);
             })?;
 
-            let domains = collect_blocked_domains(&cfg);
-            hosts_manager::apply(&domains)?;
+            hosts_manager::apply(&collect_blocklist(&cfg))?;
 
-            if let Ok(mut guard) = blocked.lock() {
-                *guard = domains;
-            }
-
-            Ok((json!({"status": "OK"}), false))
+            Ok((Response::ack(), false))
         }
 
-        "QUIT" => {
-            let pw = msg["password"].as_str().unwrap_or("");
-            let cfg = config::load()?;
-
-            if let Some(hash) = &cfg.password_hash {
-                if !password::verify(pw, hash)? {
-                    return Ok((
-                        json!({"status": "ERROR", "message": "Invalid password"}),
-                        false,
-                    ));
-                }
+        Request::Quit { password: pw } => {
+            let pw = pw.unwrap_or_default();
+            let wrong = ErrorReply::new(ErrorCode::InvalidPassword, "Invalid password");
+            if let Some(rejected) = check_password(&pw, Credential::Admin, wrong)? {
+                return Ok((rejected, false));
             }
 
             // Remove all hosts entries before shutting down.
-            let _ = hosts_manager::apply(&[]);
-            Ok((json!({"status": "OK"}), true))
+            let _ = hosts_manager::apply(&Blocklist::default());
+            Ok((Response::ack(), true))
         }
 
-        _ => Ok((
-            json!({"status": "ERROR", "message": format!("Unknown message type: {msg_type}")}),
-            false,
-        )),
+        // ---- Credentials ----
+
+        Request::ChangePassword {
+            current_password,
+            new_password,
+        } => handle_change_password(current_password.as_deref().unwrap_or(""), &new_password),
+
+        Request::SetPin {
+            password: pw,
+            label,
+            pin,
+        } => handle_set_pin(pw.as_deref().unwrap_or(""), label, &pin),
+
+        Request::RemovePin {
+            password: pw,
+            label,
+        } => handle_remove_pin(pw.as_deref().unwrap_or(""), &label),
+
+        Request::ResetPassword {
+            recovery_code,
+            new_password,
+        } => handle_reset_password(&recovery_code, &new_password),
+
+        Request::RegenerateRecoveryCodes { password: pw } => {
+            handle_regenerate_recovery_codes(pw.as_deref().unwrap_or(""))
+        }
     }
 }
 
@@ -374,42 +705,33 @@ fn handle_message(
 // GET_STATE — return full shared state for extension polling
 // =========================================================================
 
-fn handle_get_state() -> Result<(serde_json::Value, bool), AppError> {
-    let cfg = config::load()?;
-
-    let session = cfg.session.as_ref().map(|s| {
-        json!({
-            "mode": s.mode,
-            "startTime": s.start_time,
-            "endTime": s.end_time,
-            "locked": s.locked,
-            "scheduledId": s.scheduled_id,
-        })
-    });
-
-    let youtube_rules = cfg.youtube_rules.as_ref().map(|r| {
-        json!({
-            "blockedChannels": r.blocked_channels,
-            "allowedChannels": r.allowed_channels,
-        })
-    });
+fn handle_get_state() -> Result<(Response, bool), AppError> {
+    let mut cfg = config::load()?;
 
-    let settings = cfg.global_settings.as_ref().map(|s| {
-        json!({
-            "defaultMode": s.default_mode,
-            "blockAllChannels": s.block_all_channels,
-            "sessionDurationMinutes": s.session_duration_minutes,
-        })
-    });
+    // Report the cycle phase as of now, even if no loop has ticked since
+    // the last one ended.
+    if let Some(ref mut session) = cfg.session {
+        session.advance_cycle(config::now_ms());
+    }
 
     Ok((
-        json!({
-            "status": "OK",
-            "session": session,
-            "youtubeRules": youtube_rules,
-            "blockedDomains": cfg.blocked_domains,
-            "settings": settings,
-        }),
+        Response::Ok(Reply::State(Box::new(StateReply {
+            session: cfg.session.as_ref().map(SessionView::from),
+            youtube_rules: cfg.youtube_rules.as_ref().map(YoutubeRulesView::from),
+            blocked_domains: cfg.blocked_domains.clone(),
+            domain_families: cfg.domain_families.clone(),
+            blocked_hosts: collect_blocklist(&cfg).domains,
+            settings: cfg.global_settings.as_ref().map(SettingsView::from),
+            schedules: cfg.schedules.iter().map(ScheduleView::from).collect(),
+            daily_sessions: DailySessionsView {
+                used: cfg.daily_sessions.on(config::local_today()),
+                limit: cfg.daily_session_limit(),
+            },
+            password_set: cfg.password_hash.is_some(),
+            session_pins: cfg.session_pins.iter().map(|p| p.label.clone()).collect(),
+            recovery_codes_left: cfg.recovery_codes.len(),
+            config_recovered: cfg.recovered.as_ref().map(RecoveryView::from),
+        }))),
         false,
     ))
 }
@@ -419,48 +741,117 @@ fn handle_get_state() -> Result<(serde_json::Value, bool), AppError> {
 // =========================================================================
 
 fn handle_start_session(
-    msg: &serde_json::Value,
-    blocked: &Arc<Mutex<Vec<String>>>,
-) -> Result<(serde_json::Value, bool), AppError> {
-    let duration_minutes = msg["durationMinutes"].as_u64().unwrap_or(30) as u32;
-    let scheduled_id = msg["scheduledId"].as_str().map(|s| s.to_string());
-    let locked = msg["locked"].as_bool().unwrap_or(false);
-    let mode = msg["mode"].as_str().unwrap_or("precision").to_string();
+    duration_minutes: Option<u32>,
+    scheduled_id: Option<String>,
+    locked: bool,
+    mode: Option<Mode>,
+    cycle: Option<CyclePayload>,
+) -> Result<(Response, bool), AppError> {
+    let duration_minutes = duration_minutes.unwrap_or(30);
+    let mode = mode.unwrap_or(Mode::Precision);
+
+    if duration_minutes == 0 {
+        return Ok((
+            Response::error(
+                ErrorCode::InvalidMessage,
+                "durationMinutes must be at least 1",
+            ),
+            false,
+        ));
+    }
+
+    if !mode.is_active() {
+        return Ok((
+            Response::error(ErrorCode::InvalidMode, "Cannot start a session in mode 'off'"),
+            false,
+        ));
+    }
 
     let now = config::now_ms();
     let end_time = now + (duration_minutes as u64) * 60 * 1000;
 
+    let cycle = cycle.map(|c| {
+        config::Cycle::new(
+            c.work_minutes,
+            c.break_minutes,
+            c.long_break_minutes,
+            c.long_break_every,
+            now,
+        )
+    });
+    if let Some(Err(e)) = cycle.as_ref().map(config::Cycle::validate) {
+        return Ok((Response::error(ErrorCode::InvalidCycle, e), false));
+    }
+
+    // Checked and counted under the config lock, so browsers racing to
+    // start a session can't both take the last one. A running session is
+    // never replaced: ending it goes through END_SESSION's PIN and
+    // early-end checks.
+    let today = config::local_today();
+    let mut running = false;
+    let mut limit_reached = None;
+    let mut replaced = None;
     let cfg = config::update(|cfg| {
-        cfg.session = Some(config::SessionState {
-            mode: mode.clone(),
+        if cfg.session.as_ref().is_some_and(|s| s.is_running(now)) {
+            running = true;
+            return;
+        }
+        if let Some(limit) = cfg.daily_session_limit() {
+            if cfg.daily_sessions.on(today) >= limit {
+                limit_reached = Some(limit);
+                return;
+            }
+        }
+        cfg.daily_sessions.increment(today);
+
+        replaced = cfg.session.replace(config::SessionState {
+            mode,
             start_time: Some(now),
             end_time: Some(end_time),
             locked,
             scheduled_id,
+            id: Some(config::new_session_id(now)),
+            cycle,
             ..Default::default()
         });
     })?;
 
-    // Apply hosts-level blocks only in strict mode
-    let domains = collect_blocked_domains(&cfg);
-    if !domains.is_empty() {
-        hosts_manager::apply(&domains)?;
+    if running {
+        return Ok((
+            Response::error(
+                ErrorCode::SessionActive,
+                "A session is already running; end it first.",
+            ),
+            false,
+        ));
     }
-    if let Ok(mut guard) = blocked.lock() {
-        *guard = domains;
+    if let Some(limit) = limit_reached {
+        return Ok((
+            Response::Error(
+                ErrorReply::new(
+                    ErrorCode::LimitReached,
+                    format!("Daily session limit reached ({limit} per day)."),
+                )
+                .retry_after(history::ms_until_tomorrow(today, now)),
+            ),
+            false,
+        ));
+    }
+
+    // Anything left in the slot had already run out.
+    if let Some(old) = replaced {
+        history::record(&old, EndReason::expired(old.end_time, now), now)?;
     }
 
+    // Apply hosts-level blocks only in strict mode (or lift a previous
+    // strict session's blocks).
+    let dns_flush = hosts_manager::apply(&collect_blocklist(&cfg))?;
+
     let session = cfg.session.as_ref().unwrap();
     Ok((
-        json!({
-            "status": "OK",
-            "session": {
-                "mode": session.mode,
-                "startTime": session.start_time,
-                "endTime": session.end_time,
-                "locked": session.locked,
-                "scheduledId": session.scheduled_id,
-            }
+        Response::Ok(Reply::Session {
+            session: SessionView::from(session),
+            dns_flush,
         }),
         false,
     ))
@@ -470,174 +861,755 @@ fn handle_start_session(
 // END_SESSION — end the global focus session
 // =========================================================================
 
-fn handle_end_session(
-    msg: &serde_json::Value,
-    blocked: &Arc<Mutex<Vec<String>>>,
-) -> Result<(serde_json::Value, bool), AppError> {
-    let natural = msg["natural"].as_bool().unwrap_or(false);
-    let parent_pin = msg["parentPin"].as_str().unwrap_or("");
+/// Slack for the extension's timer running slightly ahead of ours.
+const NATURAL_END_GRACE_MS: u64 = 5_000;
 
+fn handle_end_session(
+    natural: bool,
+    parent_pin: &str,
+    confirmation: &str,
+    pw: &str,
+) -> Result<(Response, bool), AppError> {
     let cfg = config::load()?;
+    let now = config::now_ms();
 
-    // Check if session is locked and PIN is required
-    if let Some(ref session) = cfg.session {
-        if config::is_mode_active(&session.mode) && session.locked && !natural {
-            // Need to verify parent PIN
-            if parent_pin.is_empty() {
-                return Ok((
-                    json!({"status": "ERROR", "message": "Session is locked. PIN required."}),
-                    false,
-                ));
-            }
+    // Only take the extension's word for a natural end once our own clock
+    // agrees, otherwise `natural: true` would skip the PIN and friction.
+    let natural = natural
+        && cfg.session.as_ref().is_none_or(|s| {
+            !s.mode.is_active()
+                || s.end_time
+                    .is_some_and(|end| now + NATURAL_END_GRACE_MS >= end)
+        });
 
-            // Verify PIN against password_hash (using Argon2)
-            if let Some(ref hash) = cfg.password_hash {
-                if !password::verify(parent_pin, hash)? {
-                    return Ok((
-                        json!({"status": "ERROR", "message": "Invalid PIN."}),
-                        false,
-                    ));
-                }
-            }
+    if !natural {
+        if let Some(rejected) = check_session_pin(&cfg, parent_pin)? {
+            return Ok((rejected, false));
+        }
+        if let Some(rejected) = check_early_end(&cfg, confirmation, pw, now)? {
+            return Ok((rejected, false));
         }
     }
 
     // End the session
+    let mut ended = None;
     config::update(|cfg| {
-        cfg.session = Some(config::SessionState {
-            mode: "off".to_string(),
-            start_time: None,
-            end_time: None,
-            locked: false,
-            scheduled_id: None,
-            ..Default::default()
-        });
+        ended = cfg.session.replace(config::SessionState::default());
         cfg.blocked_domains.clear();
     })?;
 
     // Clean up hosts file
-    hosts_manager::apply(&[])?;
-    if let Ok(mut guard) = blocked.lock() {
-        guard.clear();
+    hosts_manager::apply(&Blocklist::default())?;
+
+    if let Some(session) = ended {
+        let reason = if natural {
+            EndReason::expired(session.end_time, now)
+        } else if session.locked {
+            EndReason::Pin
+        } else {
+            EndReason::Early
+        };
+        history::record(&session, reason, now)?;
     }
 
-    Ok((json!({"status": "OK", "natural": natural}), false))
+    Ok((Response::Ok(Reply::Ended { natural }), false))
+}
+
+/// If the active session is locked, verify `parent_pin` against the
+/// session PINs. Returns the error response to send when it doesn't pass.
+fn check_session_pin(cfg: &config::Config, parent_pin: &str) -> Result<Option<Response>, AppError> {
+    let locked = cfg
+        .session
+        .as_ref()
+        .is_some_and(|s| s.mode.is_active() && s.locked);
+    if !locked {
+        return Ok(None);
+    }
+
+    if parent_pin.is_empty() {
+        return Ok(Some(Response::error(
+            ErrorCode::PinRequired,
+            "Session is locked. PIN required.",
+        )));
+    }
+
+    check_password(
+        parent_pin,
+        Credential::SessionPin,
+        ErrorReply::new(ErrorCode::InvalidPin, "Invalid PIN."),
+    )
+}
+
+/// Check a password or PIN through the shared failed-attempt throttle.
+/// Returns the error response to send when it doesn't pass; `wrong` is the
+/// one for an incorrect attempt.
+fn check_password(
+    attempt: &str,
+    credential: Credential,
+    wrong: ErrorReply,
+) -> Result<Option<Response>, AppError> {
+    let rejected = match password::check(attempt, credential)? {
+        password::Verdict::Accepted => return Ok(None),
+        password::Verdict::Rejected { retry_after_ms } => match retry_after_ms {
+            Some(ms) => wrong.retry_after(ms),
+            None => wrong,
+        },
+        password::Verdict::Throttled { retry_after_ms } => ErrorReply::new(
+            ErrorCode::TooManyAttempts,
+            format!(
+                "Too many failed attempts. Try again in {} s.",
+                retry_after_ms.div_ceil(1000)
+            ),
+        )
+        .retry_after(retry_after_ms),
+    };
+    Ok(Some(Response::Error(rejected)))
+}
+
+/// Apply the early-end friction policy to an active session. Returns the
+/// error response to send when it doesn't pass.
+fn check_early_end(
+    cfg: &config::Config,
+    confirmation: &str,
+    pw: &str,
+    now: u64,
+) -> Result<Option<Response>, AppError> {
+    let Some(session) = cfg.session.as_ref().filter(|s| s.mode.is_active()) else {
+        return Ok(None);
+    };
+    let policy = cfg
+        .global_settings
+        .as_ref()
+        .map(|gs| gs.early_end.clone())
+        .unwrap_or_default();
+
+    match policy {
+        EarlyEnd::None => Ok(None),
+
+        EarlyEnd::Phrase { phrase } => {
+            if confirmation.is_empty() {
+                Ok(Some(Response::error(
+                    ErrorCode::PhraseRequired,
+                    format!("Type this phrase to end early: {phrase}"),
+                )))
+            } else if confirmation != phrase {
+                Ok(Some(Response::error(
+                    ErrorCode::InvalidPhrase,
+                    "Phrase does not match.",
+                )))
+            } else {
+                Ok(None)
+            }
+        }
+
+        EarlyEnd::Password => {
+            if cfg.password_hash.is_none() {
+                return Ok(None);
+            }
+            if pw.is_empty() {
+                return Ok(Some(Response::error(
+                    ErrorCode::PasswordRequired,
+                    "Password required to end early.",
+                )));
+            }
+            check_password(
+                pw,
+                Credential::Admin,
+                ErrorReply::new(ErrorCode::InvalidPassword, "Invalid password"),
+            )
+        }
+
+        EarlyEnd::Wait { minutes } => {
+            let wait = u64::from(minutes) * 60_000;
+            match session.end_requested_at {
+                Some(at) if now < at + wait => {
+                    let left = at + wait - now;
+                    Ok(Some(Response::Error(
+                        ErrorReply::new(
+                            ErrorCode::WaitRequired,
+                            format!(
+                                "Early end requested. Confirm in {} min.",
+                                left.div_ceil(60_000)
+                            ),
+                        )
+                        .retry_after(left),
+                    )))
+                }
+                Some(at) if now < at + wait + config::EARLY_END_CONFIRM_WINDOW_MS => Ok(None),
+                // First request, or the last one lapsed: start the countdown.
+                _ => {
+                    config::update(|cfg| {
+                        if let Some(ref mut session) = cfg.session {
+                            session.end_requested_at = Some(now);
+                        }
+                    })?;
+                    Ok(Some(Response::Error(
+                        ErrorReply::new(
+                            ErrorCode::WaitRequired,
+                            format!("Early end requested. Confirm in {minutes} min."),
+                        )
+                        .retry_after(wait),
+                    )))
+                }
+            }
+        }
+    }
 }
 
 // =========================================================================
-// SWITCH_MODE — change mode mid-session (precision ↔ strict)
+// EXTEND_SESSION — push the end of the running session back
 // =========================================================================
 
-fn handle_switch_mode(
-    msg: &serde_json::Value,
-    blocked: &Arc<Mutex<Vec<String>>>,
-) -> Result<(serde_json::Value, bool), AppError> {
-    let target_mode = msg["mode"].as_str().unwrap_or("");
-
-    if target_mode != "precision" && target_mode != "strict" {
+fn handle_extend_session(minutes: u32) -> Result<(Response, bool), AppError> {
+    if minutes == 0 {
         return Ok((
-            json!({"status": "ERROR", "message": format!("Invalid mode: {target_mode}")}),
+            Response::error(ErrorCode::InvalidMessage, "minutes must be at least 1"),
             false,
         ));
     }
 
     let cfg = config::load()?;
-
-    // Verify there's an active session to switch
-    let current_mode = cfg
-        .session
-        .as_ref()
-        .map_or("off", |s| s.mode.as_str());
-
-    if !config::is_mode_active(current_mode) {
+    if !cfg.session.as_ref().is_some_and(|s| s.mode.is_active()) {
         return Ok((
-            json!({"status": "ERROR", "message": "No active session to switch mode."}),
+            Response::error(ErrorCode::NoActiveSession, "No active session to extend."),
             false,
         ));
     }
 
-    if current_mode == target_mode {
-        return Ok((json!({"status": "OK", "mode": target_mode}), false));
-    }
-
-    // Update the mode in config
-    let target = target_mode.to_string();
-    let cfg = config::update(move |cfg| {
+    let cfg = config::update(|cfg| {
         if let Some(ref mut session) = cfg.session {
-            session.mode = target.clone();
+            session.extend(u64::from(minutes) * 60_000);
         }
     })?;
 
-    // Apply or clear hosts based on new mode
-    let domains = collect_blocked_domains(&cfg);
-    hosts_manager::apply(&domains)?;
-    if let Ok(mut guard) = blocked.lock() {
-        *guard = domains;
-    }
-
-    Ok((json!({"status": "OK", "mode": target_mode}), false))
+    // Mode and pause state are unchanged, so the hosts file is too.
+    let session = cfg.session.as_ref().unwrap();
+    Ok((
+        Response::Ok(Reply::Session {
+            session: SessionView::from(session),
+            dns_flush: Vec::new(),
+        }),
+        false,
+    ))
 }
 
 // =========================================================================
-// SYNC_RULES — extension pushes block rules to shared config
+// PAUSE_SESSION / RESUME_SESSION — stop and restart the session clock
 // =========================================================================
 
-fn handle_sync_rules(msg: &serde_json::Value) -> Result<(serde_json::Value, bool), AppError> {
-    let youtube_rules = &msg["youtubeRules"];
-    let blocked_sites = &msg["blockedSites"];
+fn handle_pause_session(parent_pin: &str) -> Result<(Response, bool), AppError> {
+    let cfg = config::load()?;
 
-    config::update(|cfg| {
-        if youtube_rules.is_object() {
-            let blocked_channels = youtube_rules["blockedChannels"]
-                .as_array()
-                .map(|a| a.iter().filter_map(|v| v.as_str().map(String::from)).collect())
-                .unwrap_or_default();
-            let allowed_channels = youtube_rules["allowedChannels"]
-                .as_array()
-                .map(|a| a.iter().filter_map(|v| v.as_str().map(String::from)).collect())
-                .unwrap_or_default();
+    let Some(session) = cfg.session.as_ref().filter(|s| s.mode.is_active()) else {
+        return Ok((
+            Response::error(ErrorCode::NoActiveSession, "No active session to pause."),
+            false,
+        ));
+    };
+    if session.is_paused() {
+        return Ok((
+            Response::error(ErrorCode::AlreadyPaused, "Session is already paused."),
+            false,
+        ));
+    }
 
-            cfg.youtube_rules = Some(config::YoutubeRules {
-                blocked_channels,
-                allowed_channels,
-            });
-        }
+    // Pausing lifts strict blocking, so a locked session needs the PIN.
+    if let Some(rejected) = check_session_pin(&cfg, parent_pin)? {
+        return Ok((rejected, false));
+    }
 
-        if let Some(sites) = blocked_sites.as_array() {
-            cfg.blocked_domains = sites
-                .iter()
-                .filter_map(|v| v.as_str().map(String::from))
+    let policy = cfg
+        .global_settings
+        .as_ref()
+        .map(|gs| gs.pause_policy.clone())
+        .unwrap_or_default();
+    if let Some(max) = policy.max_pauses {
+        if session.pause_count >= max {
+            return Ok((
+                Response::error(
+                    ErrorCode::PauseLimit,
+                    format!("Pause limit reached ({max} per session)."),
+                ),
+                false,
+            ));
+        }
+    }
+
+    let now = config::now_ms();
+    let cfg = config::update(|cfg| {
+        if let Some(ref mut session) = cfg.session {
+            session.pause(now);
+        }
+    })?;
+
+    // Lift strict-mode hosts entries for the duration of the pause.
+    let dns_flush = hosts_manager::apply(&collect_blocklist(&cfg))?;
+
+    let session = cfg.session.as_ref().unwrap();
+    Ok((
+        Response::Ok(Reply::Session {
+            session: SessionView::from(session),
+            dns_flush,
+        }),
+        false,
+    ))
+}
+
+fn handle_resume_session() -> Result<(Response, bool), AppError> {
+    let cfg = config::load()?;
+
+    if !cfg.session.as_ref().is_some_and(|s| s.is_paused()) {
+        return Ok((
+            Response::error(ErrorCode::NotPaused, "Session is not paused."),
+            false,
+        ));
+    }
+
+    let now = config::now_ms();
+    let cfg = config::update(|cfg| {
+        if let Some(ref mut session) = cfg.session {
+            session.resume(now);
+        }
+    })?;
+
+    let dns_flush = hosts_manager::apply(&collect_blocklist(&cfg))?;
+
+    let session = cfg.session.as_ref().unwrap();
+    Ok((
+        Response::Ok(Reply::Session {
+            session: SessionView::from(session),
+            dns_flush,
+        }),
+        false,
+    ))
+}
+
+// =========================================================================
+// SWITCH_MODE — change mode mid-session (precision ↔ strict)
+// =========================================================================
+
+fn handle_switch_mode(
+    target_mode: Mode,
+    parent_pin: &str,
+    confirmation: &str,
+    pw: &str,
+) -> Result<(Response, bool), AppError> {
+    if !target_mode.is_active() {
+        return Ok((
+            Response::error(ErrorCode::InvalidMode, format!("Invalid mode: {target_mode}")),
+            false,
+        ));
+    }
+
+    let cfg = config::load()?;
+
+    // Verify there's an active session to switch
+    let current_mode = cfg.session_mode();
+
+    if !current_mode.is_active() {
+        return Ok((
+            Response::error(ErrorCode::NoActiveSession, "No active session to switch mode."),
+            false,
+        ));
+    }
+
+    if current_mode == target_mode {
+        return Ok((
+            Response::Ok(Reply::Mode {
+                mode: target_mode,
+                dns_flush: Vec::new(),
+            }),
+            false,
+        ));
+    }
+
+    // Precision drops the hosts block: for a strict session that is ending
+    // it early in all but name.
+    if current_mode == Mode::Strict && target_mode == Mode::Precision {
+        if let Some(rejected) = check_session_pin(&cfg, parent_pin)? {
+            return Ok((rejected, false));
+        }
+        if let Some(rejected) = check_early_end(&cfg, confirmation, pw, config::now_ms())? {
+            return Ok((rejected, false));
+        }
+    }
+
+    // Update the mode in config
+    let cfg = config::update(move |cfg| {
+        if let Some(ref mut session) = cfg.session {
+            session.mode = target_mode;
+        }
+    })?;
+
+    // Apply or clear hosts based on new mode
+    let dns_flush = hosts_manager::apply(&collect_blocklist(&cfg))?;
+
+    Ok((
+        Response::Ok(Reply::Mode {
+            mode: target_mode,
+            dns_flush,
+        }),
+        false,
+    ))
+}
+
+// =========================================================================
+// SYNC_RULES — extension pushes block rules to shared config
+// =========================================================================
+
+fn handle_sync_rules(
+    youtube_rules: Option<YoutubeRulesPayload>,
+    blocked_sites: Option<Vec<String>>,
+    domain_families: Option<BTreeMap<String, Vec<String>>>,
+) -> Result<(Response, bool), AppError> {
+    let cfg = config::update(|cfg| {
+        if let Some(rules) = youtube_rules {
+            cfg.youtube_rules = Some(config::YoutubeRules {
+                blocked_channels: rules.blocked_channels,
+                allowed_channels: rules.allowed_channels,
+            });
+        }
+
+        if let Some(sites) = blocked_sites {
+            cfg.blocked_domains = sites;
+        }
+
+        if let Some(families) = domain_families {
+            cfg.domain_families = families
+                .into_iter()
+                .map(|(domain, members)| {
+                    let members = members.iter().map(|m| m.to_lowercase()).collect();
+                    (domain.to_lowercase(), members)
+                })
                 .collect();
         }
     })?;
 
-    Ok((json!({"status": "OK"}), false))
+    // New sites or families alter the expansion of an active strict session.
+    hosts_manager::apply(&collect_blocklist(&cfg))?;
+
+    Ok((Response::ack(), false))
 }
 
 // =========================================================================
 // SYNC_SETTINGS — extension pushes settings to shared config
 // =========================================================================
 
-fn handle_sync_settings(msg: &serde_json::Value) -> Result<(serde_json::Value, bool), AppError> {
-    let settings = &msg["settings"];
+fn handle_sync_settings(settings: SettingsPayload, pw: &str) -> Result<(Response, bool), AppError> {
+    if let Some(rejected) = check_admin(pw)? {
+        return Ok((rejected, false));
+    }
+    if let Some(ref sinks) = settings.sink_addresses {
+        if let Err(e) = config::validate_sink_addresses(sinks) {
+            return Ok((
+                Response::error(ErrorCode::InvalidSettings, e.to_string()),
+                false,
+            ));
+        }
+    }
+    if let Some(ref import) = settings.calendar_import {
+        let patterns = [&import.strict_pattern, &import.precision_pattern];
+        for pattern in patterns.into_iter().flatten() {
+            if let Err(e) = calendar::compile_pattern(pattern) {
+                return Ok((
+                    Response::error(ErrorCode::InvalidSettings, e.to_string()),
+                    false,
+                ));
+            }
+        }
+    }
+    if let Some(ref early_end) = settings.early_end {
+        if let Err(e) = early_end.validate() {
+            return Ok((Response::error(ErrorCode::InvalidSettings, e), false));
+        }
+        if *early_end == EarlyEnd::Password && config::load()?.password_hash.is_none() {
+            return Ok((
+                Response::error(
+                    ErrorCode::InvalidSettings,
+                    "early-end password requires a password (run setup first)",
+                ),
+                false,
+            ));
+        }
+    }
+    if settings.default_mode == Some(Mode::Off) {
+        return Ok((
+            Response::error(
+                ErrorCode::InvalidMode,
+                "Invalid defaultMode: off (expected precision or strict)",
+            ),
+            false,
+        ));
+    }
 
-    config::update(|cfg| {
+    let cfg = config::update(|cfg| {
         let mut gs = cfg.global_settings.clone().unwrap_or_default();
 
-        if let Some(v) = settings["defaultMode"].as_str() {
-            gs.default_mode = v.to_string();
+        if let Some(v) = settings.default_mode {
+            gs.default_mode = v;
         }
-        if let Some(v) = settings["blockAllChannels"].as_bool() {
+        if let Some(v) = settings.block_all_channels {
             gs.block_all_channels = v;
         }
-        if let Some(v) = settings["sessionDurationMinutes"].as_u64() {
-            gs.session_duration_minutes = v as u32;
+        if let Some(v) = settings.session_duration_minutes {
+            gs.session_duration_minutes = v;
+        }
+        if let Some(v) = settings.sink_addresses {
+            gs.sink_addresses = v;
+        }
+        if let Some(v) = settings.tamper_policy {
+            gs.tamper_policy = v.into();
+        }
+        if let Some(v) = settings.pause_policy {
+            gs.pause_policy = v.into();
+        }
+        if let Some(v) = settings.early_end {
+            gs.early_end = v;
+        }
+        if let Some(v) = settings.daily_session_limit {
+            gs.daily_session_limit = v;
+        }
+        if let Some(v) = settings.calendar_import {
+            if let Some(p) = v.strict_pattern {
+                gs.calendar_import.strict_pattern = p;
+            }
+            if let Some(p) = v.precision_pattern {
+                gs.calendar_import.precision_pattern = p;
+            }
         }
 
         cfg.global_settings = Some(gs);
     })?;
 
-    Ok((json!({"status": "OK"}), false))
+    // Re-render the hosts block if a strict session is running with the old sinks.
+    hosts_manager::apply(&collect_blocklist(&cfg))?;
+
+    Ok((Response::ack(), false))
+}
+
+// =========================================================================
+// Credentials — admin password and session PINs
+// =========================================================================
+
+/// Require the admin password, if one is set. Returns the error response
+/// to send when it doesn't pass.
+fn check_admin(pw: &str) -> Result<Option<Response>, AppError> {
+    if config::load()?.password_hash.is_none() {
+        return Ok(None);
+    }
+    if pw.is_empty() {
+        return Ok(Some(Response::error(
+            ErrorCode::PasswordRequired,
+            "Admin password required.",
+        )));
+    }
+    check_password(
+        pw,
+        Credential::Admin,
+        ErrorReply::new(ErrorCode::InvalidPassword, "Invalid password"),
+    )
+}
+
+fn handle_change_password(current: &str, new: &str) -> Result<(Response, bool), AppError> {
+    if let Some(rejected) = check_admin(current)? {
+        return Ok((rejected, false));
+    }
+    if let Some(rejected) = check_new_password(new)? {
+        return Ok((rejected, false));
+    }
+    if let Some(rejected) = check_pin_collision(new)? {
+        return Ok((rejected, false));
+    }
+
+    let hash = password::hash(new)?;
+    config::update(|cfg| cfg.password_hash = Some(hash))?;
+    Ok((Response::ack(), false))
+}
+
+/// Reject a new admin password that is too weak.
+fn check_new_password(new: &str) -> Result<Option<Response>, AppError> {
+    Ok(password::weakness(new, Credential::Admin)
+        .map(|reason| Response::error(ErrorCode::WeakPassword, reason)))
+}
+
+/// Reject a new admin password that equals a session PIN. Unthrottled, so
+/// only call it once the caller has proven who they are; otherwise it
+/// would confirm PIN guesses.
+fn check_pin_collision(new: &str) -> Result<Option<Response>, AppError> {
+    // A PIN that also worked as the admin password would undo the split.
+    let pins: Vec<String> = config::load()?
+        .session_pins
+        .into_iter()
+        .map(|p| p.hash)
+        .collect();
+    if password::matches_any(new, &pins)? {
+        return Ok(Some(Response::error(
+            ErrorCode::WeakPassword,
+            "Admin password must differ from every session PIN.",
+        )));
+    }
+    Ok(None)
+}
+
+fn handle_reset_password(code: &str, new: &str) -> Result<(Response, bool), AppError> {
+    // Validate first so a weak password doesn't burn the code.
+    if let Some(rejected) = check_new_password(new)? {
+        return Ok((rejected, false));
+    }
+    if let Some(rejected) = check_password(
+        code,
+        Credential::RecoveryCode,
+        ErrorReply::new(
+            ErrorCode::InvalidRecoveryCode,
+            "Invalid or already used recovery code.",
+        ),
+    )? {
+        return Ok((rejected, false));
+    }
+    // Only after the code: before it, this would answer PIN guesses for
+    // free. A collision costs the code, as a wrong guess would.
+    if let Some(rejected) = check_pin_collision(new)? {
+        return Ok((rejected, false));
+    }
+
+    let hash = password::hash(new)?;
+    let left = config::update(|cfg| cfg.password_hash = Some(hash))?
+        .recovery_codes
+        .len();
+    eprintln!("[FocusBlocker] Admin password reset with a recovery code ({left} left).");
+    Ok((Response::ack(), false))
+}
+
+fn handle_regenerate_recovery_codes(pw: &str) -> Result<(Response, bool), AppError> {
+    if config::load()?.password_hash.is_none() {
+        return Ok((
+            Response::error(
+                ErrorCode::PasswordNotSet,
+                "Set an admin password before creating recovery codes.",
+            ),
+            false,
+        ));
+    }
+    if let Some(rejected) = check_admin(pw)? {
+        return Ok((rejected, false));
+    }
+
+    let (codes, hashes) = password::recovery_codes(&config::load()?.argon2)?;
+    config::update(|cfg| cfg.recovery_codes = hashes)?;
+    Ok((
+        Response::Ok(Reply::RecoveryCodes {
+            recovery_codes: codes,
+        }),
+        false,
+    ))
+}
+
+fn handle_set_pin(pw: &str, label: String, pin: &str) -> Result<(Response, bool), AppError> {
+    // Without an admin password anyone could replace the PINs.
+    let Some(admin) = config::load()?.password_hash else {
+        return Ok((
+            Response::error(
+                ErrorCode::PasswordNotSet,
+                "Set an admin password before adding session PINs.",
+            ),
+            false,
+        ));
+    };
+    if let Some(rejected) = check_admin(pw)? {
+        return Ok((rejected, false));
+    }
+    if label.trim().is_empty() {
+        return Ok((
+            Response::error(ErrorCode::InvalidMessage, "PIN label cannot be empty."),
+            false,
+        ));
+    }
+    if let Some(reason) = password::weakness(pin, Credential::SessionPin) {
+        return Ok((Response::error(ErrorCode::WeakPassword, reason), false));
+    }
+    if password::verify(pin, &admin)? {
+        return Ok((
+            Response::error(
+                ErrorCode::WeakPassword,
+                "PIN must differ from the admin password.",
+            ),
+            false,
+        ));
+    }
+
+    let pin = config::SessionPin {
+        label,
+        hash: password::hash(pin)?,
+    };
+    config::update(
+        |cfg| match cfg.session_pins.iter_mut().find(|p| p.label == pin.label) {
+            Some(existing) => *existing = pin,
+            None => cfg.session_pins.push(pin),
+        },
+    )?;
+    Ok((Response::ack(), false))
+}
+
+fn handle_remove_pin(pw: &str, label: &str) -> Result<(Response, bool), AppError> {
+    if let Some(rejected) = check_admin(pw)? {
+        return Ok((rejected, false));
+    }
+
+    let mut found = false;
+    config::update(|cfg| {
+        let before = cfg.session_pins.len();
+        cfg.session_pins.retain(|p| p.label != label);
+        found = cfg.session_pins.len() < before;
+    })?;
+
+    if !found {
+        return Ok((
+            Response::error(ErrorCode::PinNotFound, format!("No session PIN '{label}'.")),
+            false,
+        ));
+    }
+    Ok((Response::ack(), false))
+}
+
+// =========================================================================
+// SYNC_SCHEDULES — replace the recurring session schedules
+// =========================================================================
+
+fn handle_sync_schedules(schedules: Vec<SchedulePayload>) -> Result<(Response, bool), AppError> {
+    let schedules: Vec<schedule::Schedule> = schedules.into_iter().map(Into::into).collect();
+
+    for (i, s) in schedules.iter().enumerate() {
+        let error = s.validate().err().or_else(|| {
+            schedules[..i]
+                .iter()
+                .any(|other| other.id == s.id)
+                .then(|| format!("Duplicate schedule id '{}'", s.id))
+        });
+        if let Some(message) = error {
+            return Ok((Response::error(ErrorCode::InvalidSchedule, message), false));
+        }
+    }
+
+    config::update(|cfg| {
+        // Keep run markers so re-syncing doesn't restart a window that was
+        // already started (and maybe ended early), and keep where imported
+        // schedules came from for senders that don't echo it back.
+        let previous = std::mem::take(&mut cfg.schedules);
+        cfg.schedules = schedules
+            .into_iter()
+            .map(|mut s| {
+                if let Some(old) = previous.iter().find(|old| old.id == s.id) {
+                    s.last_run = old.last_run;
+                    if s.source.is_none() {
+                        s.source = old.source.clone();
+                    }
+                }
+                s
+            })
+            .collect();
+    })?;
+
+    Ok((Response::ack(), false))
 }
 
 // =========================================================================
@@ -645,48 +1617,403 @@ fn handle_sync_settings(msg: &serde_json::Value) -> Result<(serde_json::Value, b
 // =========================================================================
 
 fn handle_register_extension(
-    msg: &serde_json::Value,
-) -> Result<(serde_json::Value, bool), AppError> {
-    let extension_id = msg["extensionId"]
-        .as_str()
-        .unwrap_or("")
-        .to_string();
-
+    extension_id: &str,
+    manifest_path: &str,
+) -> Result<(Response, bool), AppError> {
     if extension_id.is_empty() {
         return Ok((
-            json!({"status": "ERROR", "message": "Missing extensionId"}),
+            Response::error(ErrorCode::InvalidMessage, "Missing extensionId"),
             false,
         ));
     }
 
     #[cfg(windows)]
     {
-        let manifest_path = msg["manifestPath"].as_str().unwrap_or("").to_string();
-        if let Err(e) = registry::register_extension(&extension_id, &manifest_path) {
+        if let Err(e) = registry::register_extension(extension_id, manifest_path) {
             return Ok((
-                json!({"status": "ERROR", "message": format!("Registry error: {e}")}),
+                Response::error(ErrorCode::Registry, format!("Registry error: {e}")),
                 false,
             ));
         }
-        Ok((json!({"status": "OK"}), false))
+        Ok((Response::ack(), false))
     }
 
     #[cfg(not(windows))]
     {
+        let _ = manifest_path;
         Ok((
-            json!({"status": "ERROR", "message": "REGISTER_EXTENSION is Windows-only"}),
+            Response::error(ErrorCode::Unsupported, "REGISTER_EXTENSION is Windows-only"),
             false,
         ))
     }
 }
 
-// =========================================================================
-// Helpers
-// =========================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use platform::sandbox::Sandbox;
+
+    fn send(request: Request) -> Response {
+        handle_message(request).unwrap().0
+    }
+
+    fn error_code(response: &Response) -> Option<ErrorCode> {
+        match response {
+            Response::Error(e) => Some(e.code),
+            Response::Ok(_) => None,
+        }
+    }
+
+    fn start(minutes: u32) -> Request {
+        Request::StartSession {
+            duration_minutes: Some(minutes),
+            scheduled_id: None,
+            locked: false,
+            mode: None,
+            cycle: None,
+        }
+    }
+
+    fn pause() -> Request {
+        Request::PauseSession { parent_pin: None }
+    }
+
+    /// Admin password "correct horse" and one session PIN "2580", hashed
+    /// cheaply enough for debug builds.
+    fn with_credentials() {
+        let policy = config::Argon2Policy {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+            target_ms: 0,
+        };
+        config::update(|cfg| {
+            cfg.password_hash = Some(password::hash_with("correct horse", &policy).unwrap());
+            cfg.session_pins = vec![config::SessionPin {
+                label: "kids".into(),
+                hash: password::hash_with("2580", &policy).unwrap(),
+            }];
+            cfg.argon2 = policy;
+        })
+        .unwrap();
+    }
+
+    fn end(parent_pin: &str) -> Request {
+        Request::EndSession {
+            natural: false,
+            parent_pin: Some(parent_pin.into()),
+            confirmation: None,
+            password: None,
+        }
+    }
+
+    fn start_locked() -> Request {
+        Request::StartSession {
+            duration_minutes: Some(30),
+            scheduled_id: None,
+            locked: true,
+            mode: None,
+            cycle: None,
+        }
+    }
+
+    #[test]
+    fn pauses_are_limited_per_session() {
+        let _sandbox = Sandbox::new();
+        config::update(|cfg| {
+            cfg.global_settings = Some(config::GlobalSettings {
+                pause_policy: config::PausePolicy {
+                    max_pauses: Some(2),
+                    max_pause_minutes: None,
+                },
+                ..Default::default()
+            });
+        })
+        .unwrap();
+        assert_eq!(error_code(&send(pause())), Some(ErrorCode::NoActiveSession));
+        send(start(30));
+
+        for _ in 0..2 {
+            assert_eq!(error_code(&send(pause())), None);
+            assert_eq!(error_code(&send(pause())), Some(ErrorCode::AlreadyPaused));
+            assert_eq!(error_code(&send(Request::ResumeSession {})), None);
+        }
+        assert_eq!(error_code(&send(pause())), Some(ErrorCode::PauseLimit));
+        assert_eq!(config::load().unwrap().session.unwrap().pause_count, 2);
+    }
+
+    #[test]
+    fn resume_requires_a_pause() {
+        let _sandbox = Sandbox::new();
+        let resume = || error_code(&send(Request::ResumeSession {}));
+        assert_eq!(resume(), Some(ErrorCode::NotPaused));
+        send(start(30));
+        let end_time = config::load().unwrap().session.unwrap().end_time;
+        assert_eq!(resume(), Some(ErrorCode::NotPaused));
+        assert_eq!(config::load().unwrap().session.unwrap().end_time, end_time);
+    }
+
+    #[test]
+    fn running_session_is_not_replaced() {
+        let _sandbox = Sandbox::new();
+        send(Request::StartSession {
+            duration_minutes: Some(30),
+            scheduled_id: None,
+            locked: true,
+            mode: None,
+            cycle: None,
+        });
+        let running = config::load().unwrap().session.unwrap();
+
+        assert_eq!(error_code(&send(start(5))), Some(ErrorCode::SessionActive));
+        send(pause());
+        assert_eq!(error_code(&send(start(5))), Some(ErrorCode::SessionActive));
+
+        let cfg = config::load().unwrap();
+        let session = cfg.session.unwrap();
+        assert_eq!(session.id, running.id);
+        assert!(session.locked);
+        assert_eq!(cfg.daily_sessions.on(config::local_today()), 1);
+    }
+
+    #[test]
+    fn expired_session_is_replaced() {
+        let _sandbox = Sandbox::new();
+        send(start(30));
+        config::update(|cfg| cfg.session.as_mut().unwrap().end_time = Some(1)).unwrap();
+
+        assert_eq!(error_code(&send(start(5))), None);
+        let session = config::load().unwrap().session.unwrap();
+        assert!(session.end_time.unwrap() > config::now_ms());
+    }
+
+    #[test]
+    fn daily_limit_refuses_until_midnight() {
+        let _sandbox = Sandbox::new();
+        let limit = config::load().unwrap().daily_session_limit().unwrap();
+        for _ in 0..limit {
+            assert_eq!(error_code(&send(start(5))), None);
+            config::update(|cfg| cfg.session.as_mut().unwrap().end_time = Some(1)).unwrap();
+        }
+
+        let Response::Error(refused) = send(start(5)) else {
+            panic!("session started past the daily limit");
+        };
+        assert_eq!(refused.code, ErrorCode::LimitReached);
+        let retry = refused.retry_after_ms.unwrap();
+        assert!(retry > 0 && retry <= 25 * 3_600_000);
+        let used = config::load().unwrap().daily_sessions;
+        assert_eq!(used.on(config::local_today()), limit);
+    }
+
+    #[test]
+    fn zero_minute_session_is_rejected() {
+        let _sandbox = Sandbox::new();
+        assert_eq!(error_code(&send(start(0))), Some(ErrorCode::InvalidMessage));
+        assert!(config::load().unwrap().session.is_none());
+        assert_eq!(error_code(&send(start(1))), None);
+    }
 
-fn require_field(msg: &serde_json::Value, field: &str) -> Result<String, AppError> {
-    msg[field]
-        .as_str()
-        .map(|s| s.to_lowercase())
-        .ok_or_else(|| AppError::Messaging(format!("Missing '{field}' field")))
+    #[test]
+    fn pin_cannot_do_admin_actions() {
+        let _sandbox = Sandbox::new();
+        with_credentials();
+        let quit = |pw: &str| Request::Quit {
+            password: Some(pw.into()),
+        };
+        let regenerate = |pw: &str| Request::RegenerateRecoveryCodes {
+            password: Some(pw.into()),
+        };
+        let remove = |pw: &str| Request::RemovePin {
+            password: Some(pw.into()),
+            label: "kids".into(),
+        };
+
+        // Each refusal is followed by a success, which clears the backoff.
+        assert_eq!(
+            error_code(&send(quit("2580"))),
+            Some(ErrorCode::InvalidPassword)
+        );
+        assert_eq!(
+            error_code(&send(regenerate("2580"))),
+            Some(ErrorCode::InvalidPassword)
+        );
+        assert_eq!(error_code(&send(regenerate("correct horse"))), None);
+        assert_eq!(
+            error_code(&send(remove("2580"))),
+            Some(ErrorCode::InvalidPassword)
+        );
+        assert_eq!(error_code(&send(remove("correct horse"))), None);
+        assert!(config::load().unwrap().session_pins.is_empty());
+        assert_eq!(error_code(&send(quit("correct horse"))), None);
+    }
+
+    #[test]
+    fn admin_password_is_not_a_session_pin() {
+        let _sandbox = Sandbox::new();
+        with_credentials();
+        send(start_locked());
+
+        let pause_with = |pin: &str| Request::PauseSession {
+            parent_pin: Some(pin.into()),
+        };
+        assert_eq!(
+            error_code(&send(pause_with("correct horse"))),
+            Some(ErrorCode::InvalidPin)
+        );
+        assert_eq!(error_code(&send(pause_with("2580"))), None);
+        assert_eq!(error_code(&send(Request::ResumeSession {})), None);
+
+        assert_eq!(
+            error_code(&send(end("correct horse"))),
+            Some(ErrorCode::InvalidPin)
+        );
+        assert!(config::load().unwrap().session.unwrap().mode.is_active());
+        assert_eq!(error_code(&send(end("2580"))), None);
+    }
+
+    #[test]
+    fn admin_password_unlocks_without_session_pins() {
+        let _sandbox = Sandbox::new();
+        with_credentials();
+        config::update(|cfg| cfg.session_pins.clear()).unwrap();
+        send(start_locked());
+
+        assert_eq!(error_code(&send(end(""))), Some(ErrorCode::PinRequired));
+        assert_eq!(error_code(&send(end("2580"))), Some(ErrorCode::InvalidPin));
+        assert_eq!(error_code(&send(end("correct horse"))), None);
+    }
+
+    #[test]
+    fn reset_checks_the_code_before_the_pins() {
+        let _sandbox = Sandbox::new();
+        with_credentials();
+        let cfg = config::load().unwrap();
+        let (codes, hashes) = password::recovery_codes(&cfg.argon2).unwrap();
+        let pin = password::hash_with("owl-2580", &cfg.argon2).unwrap();
+        config::update(|cfg| {
+            cfg.recovery_codes = hashes;
+            cfg.session_pins[0].hash = pin;
+        })
+        .unwrap();
+        let reset = |code: &str| Request::ResetPassword {
+            recovery_code: code.into(),
+            new_password: "owl-2580".into(),
+        };
+
+        // A wrong code says nothing about whether the guess is a PIN.
+        assert_eq!(
+            error_code(&send(reset("wrong"))),
+            Some(ErrorCode::InvalidRecoveryCode)
+        );
+        assert_eq!(
+            error_code(&send(reset(&codes[0]))),
+            Some(ErrorCode::WeakPassword)
+        );
+    }
+
+    #[test]
+    fn recovery_is_reported_until_dismissed() {
+        let _sandbox = Sandbox::new();
+        config::update(|cfg| cfg.blocked_domains = vec!["a.com".into()]).unwrap();
+        let dir = platform::config_dir();
+        std::fs::copy(dir.join("config.json"), dir.join("config.json.bak.1")).unwrap();
+        std::fs::write(dir.join("config.json"), "{").unwrap();
+
+        let recovered = || match send(Request::GetState {}) {
+            Response::Ok(Reply::State(state)) => state.config_recovered.is_some(),
+            other => panic!("unexpected {other:?}"),
+        };
+        assert!(recovered());
+        // Other writes keep it, so it isn't gone before anyone looks.
+        send(start(30));
+        assert!(recovered());
+
+        assert_eq!(error_code(&send(Request::DismissRecovery {})), None);
+        assert!(!recovered());
+        assert!(config::load().unwrap().recovered.is_none());
+    }
+
+    #[test]
+    fn ticker_ends_overlong_pauses() {
+        let _sandbox = Sandbox::new();
+        config::update(|cfg| {
+            cfg.blocked_domains = vec!["youtube.com".into()];
+            cfg.global_settings = Some(config::GlobalSettings {
+                pause_policy: config::PausePolicy {
+                    max_pauses: None,
+                    max_pause_minutes: Some(5),
+                },
+                ..Default::default()
+            });
+        })
+        .unwrap();
+        send(Request::StartSession {
+            duration_minutes: Some(30),
+            scheduled_id: None,
+            locked: false,
+            mode: Some(config::Mode::Strict),
+            cycle: None,
+        });
+        send(pause());
+        let hosts = || std::fs::read_to_string(platform::hosts_file_path()).unwrap();
+        assert!(!hosts().contains("youtube.com"));
+
+        tick().unwrap();
+        assert!(config::load().unwrap().session.unwrap().paused_at.is_some());
+
+        config::update(|cfg| {
+            let session = cfg.session.as_mut().unwrap();
+            session.paused_at = session.paused_at.map(|at| at - 5 * 60 * 1000);
+        })
+        .unwrap();
+        tick().unwrap();
+        assert!(config::load().unwrap().session.unwrap().paused_at.is_none());
+        assert!(hosts().contains("youtube.com"));
+    }
+
+    #[test]
+    fn strict_session_is_not_downgraded_without_its_checks() {
+        let _sandbox = Sandbox::new();
+        with_credentials();
+        config::update(|cfg| {
+            cfg.global_settings = Some(config::GlobalSettings {
+                early_end: EarlyEnd::Phrase {
+                    phrase: "I give up".into(),
+                },
+                ..Default::default()
+            });
+        })
+        .unwrap();
+        send(Request::StartSession {
+            duration_minutes: Some(30),
+            scheduled_id: None,
+            locked: true,
+            mode: Some(Mode::Strict),
+            cycle: None,
+        });
+        let switch = |mode, pin: &str, phrase: &str| Request::SwitchMode {
+            mode,
+            parent_pin: Some(pin.into()),
+            confirmation: Some(phrase.into()),
+            password: None,
+        };
+        let down = |pin: &str, phrase: &str| switch(Mode::Precision, pin, phrase);
+
+        let refused = [
+            (down("", "I give up"), ErrorCode::PinRequired),
+            (down("correct horse", "I give up"), ErrorCode::InvalidPin),
+            (down("2580", ""), ErrorCode::PhraseRequired),
+        ];
+        for (request, code) in refused {
+            assert_eq!(error_code(&send(request)), Some(code));
+            assert_eq!(config::load().unwrap().session_mode(), Mode::Strict);
+        }
+
+        assert_eq!(error_code(&send(down("2580", "I give up"))), None);
+        assert_eq!(config::load().unwrap().session_mode(), Mode::Precision);
+        // Back up to strict needs nothing.
+        assert_eq!(error_code(&send(switch(Mode::Strict, "", ""))), None);
+        assert_eq!(config::load().unwrap().session_mode(), Mode::Strict);
+    }
 }
@@ -0,0 +1,344 @@
+//! Config schema versioning.
+//!
+//! Migrations run on the raw JSON document before it is deserialized into
+//! `config::Config`, so legacy fields never have to live on the data model.
+//! Each step upgrades exactly one version; `migrate` runs every step from
+//! the document's `schema_version` up to `CURRENT_SCHEMA_VERSION`.
+//!
+//! Files written before versioning existed have no `schema_version` and are
+//! treated as version 1.
+//!
+//! To add a schema change: append a step to `MIGRATIONS`, bump
+//! `CURRENT_SCHEMA_VERSION`, and add a test for the step.
+
+use crate::AppError;
+use serde_json::{Map, Value};
+
+/// Schema version written by this binary.
+pub const CURRENT_SCHEMA_VERSION: u32 = 3;
+
+/// Version assumed for documents without a `schema_version` field.
+const UNVERSIONED: u32 = 1;
+
+struct Migration {
+    /// Version this step upgrades from (to `from + 1`).
+    from: u32,
+    apply: fn(&mut Map<String, Value>),
+}
+
+/// Ordered chain of migration steps, one per version bump.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 1,
+        apply: v1_to_v2,
+    },
+    Migration {
+        from: 2,
+        apply: v2_to_v3,
+    },
+];
+
+/// Upgrade a config document in place to `CURRENT_SCHEMA_VERSION`.
+///
+/// Refuses documents written by a newer binary — silently dropping fields
+/// we don't understand could lose data on the next write.
+pub fn migrate(doc: &mut Value) -> Result<(), AppError> {
+    let obj = doc
+        .as_object_mut()
+        .ok_or_else(|| AppError::Config("Config root must be a JSON object".into()))?;
+
+    let mut version = match obj.get("schema_version") {
+        None => UNVERSIONED,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| AppError::Config(format!("Invalid schema_version: {v}")))?,
+    };
+
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(AppError::Config(format!(
+            "Config schema version {version} is newer than this binary supports \
+             ({CURRENT_SCHEMA_VERSION}). Upgrade focus-blocker-native."
+        )));
+    }
+
+    let start = version;
+    for step in MIGRATIONS.iter().filter(|m| m.from >= start) {
+        if step.from != version {
+            return Err(AppError::Config(format!(
+                "No migration from schema version {version}"
+            )));
+        }
+        (step.apply)(obj);
+        version += 1;
+    }
+
+    obj.insert("schema_version".into(), Value::from(version));
+    Ok(())
+}
+
+// =========================================================================
+// Steps
+// =========================================================================
+
+/// v1 → v2: replace the `active` / `strict_mode` booleans with modes.
+///
+///   session.active + global_settings.strict_mode → session.mode
+///   global_settings.strict_mode                  → global_settings.default_mode
+///   global_settings.block_youtube_fallback       → removed (strict mode IS the fallback)
+fn v1_to_v2(cfg: &mut Map<String, Value>) {
+    let was_strict = cfg
+        .get("global_settings")
+        .and_then(|gs| gs.get("strict_mode"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+
+    if let Some(session) = cfg.get_mut("session").and_then(Value::as_object_mut) {
+        let active = session.remove("active").and_then(|v| v.as_bool());
+        let mode_off = session
+            .get("mode")
+            .is_none_or(|m| m.as_str() == Some("off"));
+
+        if active == Some(true) && mode_off {
+            let mode = if was_strict { "strict" } else { "precision" };
+            session.insert("mode".into(), Value::from(mode));
+        }
+    }
+
+    if let Some(gs) = cfg.get_mut("global_settings").and_then(Value::as_object_mut) {
+        if let Some(strict) = gs.remove("strict_mode").and_then(|v| v.as_bool()) {
+            let default_precision = gs
+                .get("default_mode")
+                .is_none_or(|m| m.as_str() == Some("precision"));
+            if strict && default_precision {
+                gs.insert("default_mode".into(), Value::from("strict"));
+            }
+        }
+        gs.remove("block_youtube_fallback");
+    }
+}
+
+/// v2 → v3: every field added since v2, all defaulted when missing.
+///
+///   session_pins, recovery_codes, failed_attempts,
+///   argon2                                          → credentials
+///   domain_families                                 → custom subdomain families
+///   schedules                                       → recurring sessions
+///   daily_sessions                                  → daily session limit
+///   recovered                                       → backup recovery notice
+///   session.id, session.tamper_count                → session history, tamper log
+///   session.paused_at, session.paused_remaining_ms,
+///   session.pause_count, session.paused_ms          → pause and resume
+///   session.end_requested_at                        → early-end wait
+///   session.cycle                                   → work/break cycles
+///   global_settings.sink_addresses, tamper_policy,
+///   calendar_import, pause_policy, early_end,
+///   daily_session_limit                             → their settings
+///
+/// Nothing to rewrite; the bump is what matters. A v2 binary refuses the
+/// file instead of loading it and dropping these fields on its next write.
+fn v2_to_v3(_cfg: &mut Map<String, Value>) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, GlobalSettings, SessionState, YoutubeRules};
+    use serde_json::json;
+    use std::collections::BTreeSet;
+
+    /// Fields a v2 binary knows, as `object.field` below the top level.
+    const V2_FIELDS: &[&str] = &[
+        "schema_version",
+        "password_hash",
+        "blocked_domains",
+        "session",
+        "youtube_rules",
+        "global_settings",
+        "session.mode",
+        "session.start_time",
+        "session.end_time",
+        "session.locked",
+        "session.scheduled_id",
+        "youtube_rules.blocked_channels",
+        "youtube_rules.allowed_channels",
+        "global_settings.default_mode",
+        "global_settings.block_all_channels",
+        "global_settings.session_duration_minutes",
+    ];
+
+    /// Fields added by v3 — the list in `v2_to_v3`'s doc.
+    const V3_FIELDS: &[&str] = &[
+        "session_pins",
+        "recovery_codes",
+        "failed_attempts",
+        "argon2",
+        "domain_families",
+        "schedules",
+        "daily_sessions",
+        "recovered",
+        "session.id",
+        "session.tamper_count",
+        "session.paused_at",
+        "session.paused_remaining_ms",
+        "session.pause_count",
+        "session.paused_ms",
+        "session.end_requested_at",
+        "session.cycle",
+        "global_settings.sink_addresses",
+        "global_settings.tamper_policy",
+        "global_settings.calendar_import",
+        "global_settings.pause_policy",
+        "global_settings.early_end",
+        "global_settings.daily_session_limit",
+    ];
+
+    /// Every field of a fully populated config, in `V2_FIELDS` notation.
+    /// Objects that are new in v3 count as one field.
+    fn current_fields() -> (Value, BTreeSet<String>) {
+        let cfg = Config {
+            session: Some(SessionState::default()),
+            youtube_rules: Some(YoutubeRules::default()),
+            global_settings: Some(GlobalSettings::default()),
+            ..Default::default()
+        };
+        let doc = serde_json::to_value(cfg).unwrap();
+        let mut fields = BTreeSet::new();
+        for (key, value) in doc.as_object().unwrap() {
+            fields.insert(key.clone());
+            if !V2_FIELDS.contains(&key.as_str()) {
+                continue;
+            }
+            for inner in value.as_object().into_iter().flat_map(|o| o.keys()) {
+                fields.insert(format!("{key}.{inner}"));
+            }
+        }
+        (doc, fields)
+    }
+
+    fn field<'a>(doc: &'a Value, path: &str) -> Option<&'a Value> {
+        path.split('.').try_fold(doc, |v, key| v.get(key))
+    }
+
+    #[test]
+    fn chain_is_contiguous() {
+        for (i, step) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(step.from, UNVERSIONED + i as u32);
+        }
+        assert_eq!(
+            UNVERSIONED + MIGRATIONS.len() as u32,
+            CURRENT_SCHEMA_VERSION
+        );
+    }
+
+    #[test]
+    fn unversioned_is_stamped_current() {
+        let mut doc = json!({"blocked_domains": ["reddit.com"]});
+        migrate(&mut doc).unwrap();
+        assert_eq!(doc["schema_version"], CURRENT_SCHEMA_VERSION);
+        assert_eq!(doc["blocked_domains"], json!(["reddit.com"]));
+    }
+
+    #[test]
+    fn current_version_is_untouched() {
+        let mut doc = json!({
+            "schema_version": CURRENT_SCHEMA_VERSION,
+            "session": {"mode": "strict", "active": true},
+        });
+        migrate(&mut doc).unwrap();
+        // No step runs, so even a stray legacy field is left alone.
+        assert_eq!(doc["session"]["active"], true);
+    }
+
+    #[test]
+    fn newer_version_is_refused() {
+        let mut doc = json!({"schema_version": CURRENT_SCHEMA_VERSION + 1});
+        assert!(migrate(&mut doc).is_err());
+    }
+
+    #[test]
+    fn invalid_version_is_refused() {
+        let mut doc = json!({"schema_version": "two"});
+        assert!(migrate(&mut doc).is_err());
+    }
+
+    #[test]
+    fn v1_to_v2_active_session_with_strict_mode() {
+        let mut doc = json!({
+            "session": {"active": true, "start_time": 1},
+            "global_settings": {"strict_mode": true, "block_youtube_fallback": true},
+        });
+        v1_to_v2(doc.as_object_mut().unwrap());
+        assert_eq!(
+            doc,
+            json!({
+                "session": {"mode": "strict", "start_time": 1},
+                "global_settings": {"default_mode": "strict"},
+            })
+        );
+    }
+
+    #[test]
+    fn v1_to_v2_active_session_defaults_to_precision() {
+        let mut doc = json!({"session": {"mode": "off", "active": true}});
+        v1_to_v2(doc.as_object_mut().unwrap());
+        assert_eq!(doc, json!({"session": {"mode": "precision"}}));
+    }
+
+    #[test]
+    fn v1_to_v2_inactive_session_stays_off() {
+        let mut doc = json!({
+            "session": {"active": false},
+            "global_settings": {"strict_mode": false},
+        });
+        v1_to_v2(doc.as_object_mut().unwrap());
+        assert_eq!(doc, json!({"session": {}, "global_settings": {}}));
+    }
+
+    #[test]
+    fn v1_to_v2_keeps_explicit_modes() {
+        let mut doc = json!({
+            "session": {"mode": "precision", "active": true},
+            "global_settings": {"default_mode": "precision", "strict_mode": false},
+        });
+        v1_to_v2(doc.as_object_mut().unwrap());
+        assert_eq!(doc["session"]["mode"], "precision");
+        assert_eq!(doc["global_settings"]["default_mode"], "precision");
+    }
+
+    #[test]
+    fn every_field_since_v2_is_listed() {
+        // A field missing here was added without a schema bump.
+        let (_, fields) = current_fields();
+        let listed: BTreeSet<String> = V2_FIELDS
+            .iter()
+            .chain(V3_FIELDS)
+            .map(|f| f.to_string())
+            .collect();
+        assert_eq!(fields, listed);
+    }
+
+    #[test]
+    fn v2_to_v3_defaults_every_new_field() {
+        let mut doc = json!({
+            "schema_version": 2,
+            "password_hash": "$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA",
+            "session": {"mode": "precision", "start_time": 1, "locked": true},
+            "youtube_rules": {},
+            "global_settings": {"default_mode": "strict"},
+        });
+        let before = doc.clone();
+        migrate(&mut doc).unwrap();
+        assert_eq!(doc["schema_version"], 3);
+        assert_eq!(doc["session"], before["session"]);
+        assert_eq!(doc["global_settings"], before["global_settings"]);
+
+        let cfg: Config = serde_json::from_value(doc).unwrap();
+        let loaded = serde_json::to_value(cfg).unwrap();
+        let (defaults, _) = current_fields();
+        for path in V3_FIELDS {
+            assert_eq!(field(&loaded, path), field(&defaults, path), "{path}");
+        }
+        assert_eq!(loaded["password_hash"], before["password_hash"]);
+        assert_eq!(loaded["session"]["locked"], true);
+    }
+}
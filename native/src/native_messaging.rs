@@ -4,6 +4,7 @@
 //! followed by UTF-8 JSON of that length.
 
 use crate::AppError;
+use serde::Serialize;
 use std::io::{Read, Write};
 
 /// Maximum accepted message size (1 MiB). Chrome's own limit is 1 MB.
@@ -29,7 +30,7 @@ pub fn read_message(reader: &mut impl Read) -> Result<serde_json::Value, AppErro
 }
 
 /// Write one length-prefixed JSON message to the given writer.
-pub fn write_message(writer: &mut impl Write, msg: &serde_json::Value) -> Result<(), AppError> {
+pub fn write_message(writer: &mut impl Write, msg: &impl Serialize) -> Result<(), AppError> {
     let data = serde_json::to_vec(msg)?;
     writer.write_all(&(data.len() as u32).to_ne_bytes())?;
     writer.write_all(&data)?;
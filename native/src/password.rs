@@ -1,19 +1,132 @@
 //! Password hashing and verification via Argon2.
+//!
+//! Every password and PIN check goes through `check`, which throttles
+//! failed attempts with counters kept in the config file: a few free
+//! tries, then an exponentially growing wait, then a lockout. Admin
+//! password checks, session PIN checks and recovery codes are counted
+//! separately.
+//!
+//! Recovery codes reset a forgotten admin password. They are shown once,
+//! stored only as Argon2 hashes, and each works a single time.
+//!
+//! New passwords and PINs must pass `weakness` before they are hashed.
+//!
+//! Hashes are made with the config's `Argon2Policy`, which `setup` and the
+//! `calibrate` command fit to this machine. A hash made with cheaper
+//! parameters than the policy is replaced after its next successful
+//! `check`, so older installs get stronger once they are calibrated.
 
+use crate::config::{self, Argon2Policy, AttemptCount, Config, FailedAttempts};
 use crate::AppError;
-use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
 use argon2::password_hash::SaltString;
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use std::time::Instant;
 
-/// Hash a plaintext password with Argon2id and a random salt.
+/// Failures allowed before any waiting.
+const FREE_ATTEMPTS: u32 = 3;
+/// Failures after which every further attempt is locked out.
+const LOCKOUT_AFTER: u32 = 10;
+const LOCKOUT_MS: u64 = 30 * 60 * 1000;
+
+/// Calibration bounds: never cheaper than the crate default, and capped
+/// so a mis-measured run can't make every check take minutes.
+const MIN_ITERATIONS: u32 = Params::DEFAULT_T_COST;
+const MAX_ITERATIONS: u32 = 64;
+
+const MIN_PASSWORD_LEN: usize = 8;
+const MIN_PIN_LEN: usize = 4;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+/// No 0/o, 1/l/i: codes are read off paper.
+const RECOVERY_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Which stored credentials an attempt may match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Credential {
+    /// The admin password only.
+    Admin,
+    /// Any session PIN. While none is set the admin password stands in,
+    /// so a locked session is never open to anyone.
+    SessionPin,
+    /// An unused recovery code, which is used up by a match.
+    RecoveryCode,
+}
+
+/// Outcome of a throttled check.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Accepted,
+    /// Wrong password; `retry_after_ms` is set when the next attempt has to
+    /// wait.
+    Rejected {
+        retry_after_ms: Option<u64>,
+    },
+    /// Not checked: still waiting out an earlier failure.
+    Throttled {
+        retry_after_ms: u64,
+    },
+}
+
+/// Hash a plaintext password with Argon2id, a random salt and the
+/// configured policy.
 pub fn hash(password: &str) -> Result<String, AppError> {
+    hash_with(password, &config::load()?.argon2)
+}
+
+/// Hash with an explicit policy, for callers already holding the config.
+pub fn hash_with(password: &str, policy: &Argon2Policy) -> Result<String, AppError> {
+    let params = Params::new(
+        policy.memory_kib,
+        policy.iterations,
+        policy.parallelism,
+        None,
+    )
+    .map_err(|e| AppError::Config(format!("Invalid Argon2 parameters: {e}")))?;
     let salt = SaltString::generate(&mut OsRng);
-    let hash = Argon2::default()
+    let hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
         .hash_password(password.as_bytes(), &salt)
         .map_err(|e| AppError::Password(format!("Hashing failed: {e}")))?;
     Ok(hash.to_string())
 }
 
+/// True if `hash_str` is cheaper to brute-force than `policy` allows.
+pub fn needs_rehash(hash_str: &str, policy: &Argon2Policy) -> bool {
+    let Ok(parsed) = PasswordHash::new(hash_str) else {
+        return false;
+    };
+    let Ok(params) = Params::try_from(&parsed) else {
+        return false;
+    };
+    parsed.algorithm != Algorithm::Argon2id.ident()
+        || params.m_cost() < policy.memory_kib
+        || params.t_cost() < policy.iterations
+}
+
+/// `policy` with `iterations` chosen so one hash takes about `target_ms`
+/// on this machine.
+pub fn calibrate(policy: &Argon2Policy) -> Result<Argon2Policy, AppError> {
+    // A single pass includes the fixed setup cost, so the first estimate
+    // runs short; time it once more and correct.
+    let mut iterations = 1;
+    for _ in 0..2 {
+        let probe = Argon2Policy {
+            iterations,
+            ..policy.clone()
+        };
+        let start = Instant::now();
+        hash_with("calibration", &probe)?;
+        let elapsed_ms = (start.elapsed().as_millis() as u64).max(1);
+
+        iterations = (u64::from(iterations) * policy.target_ms / elapsed_ms)
+            .clamp(MIN_ITERATIONS.into(), MAX_ITERATIONS.into()) as u32;
+    }
+    Ok(Argon2Policy {
+        iterations,
+        ..policy.clone()
+    })
+}
+
 /// Verify a plaintext password against a stored Argon2 hash string.
 pub fn verify(password: &str, hash_str: &str) -> Result<bool, AppError> {
     let parsed = PasswordHash::new(hash_str)
@@ -22,3 +135,417 @@ pub fn verify(password: &str, hash_str: &str) -> Result<bool, AppError> {
         .verify_password(password.as_bytes(), &parsed)
         .is_ok())
 }
+
+/// Check `attempt` against the stored `credential`, counting failures.
+/// With nothing stored, everything is accepted. A matched hash weaker than
+/// the policy is rehashed in place.
+///
+/// The Argon2 work is done before taking the config lock, which is then
+/// held only to record the outcome, and only if that changes anything.
+pub fn check(attempt: &str, credential: Credential) -> Result<Verdict, AppError> {
+    let now = config::now_ms();
+    let cfg = config::load()?;
+
+    let hashes = stored(&cfg, credential);
+    if hashes.is_empty() {
+        // No recovery codes means no way in, not an open door.
+        return Ok(match credential {
+            Credential::RecoveryCode => Verdict::Rejected {
+                retry_after_ms: None,
+            },
+            _ => Verdict::Accepted,
+        });
+    }
+    let failed = counter(&cfg.failed_attempts, credential);
+    if let Some(retry_at) = retry_at(failed).filter(|&at| now < at) {
+        return Ok(Verdict::Throttled {
+            retry_after_ms: retry_at - now,
+        });
+    }
+
+    let attempt = match credential {
+        Credential::RecoveryCode => normalize_recovery_code(attempt),
+        _ => attempt.to_string(),
+    };
+    let Some(i) = position(&attempt, &hashes)? else {
+        let mut retry_after_ms = None;
+        config::update(|cfg| {
+            let failed = counter_mut(&mut cfg.failed_attempts, credential);
+            failed.count += 1;
+            failed.last_at = Some(now);
+            retry_after_ms = retry_at(failed).map(|at| at - now);
+        })?;
+        return Ok(Verdict::Rejected { retry_after_ms });
+    };
+
+    let matched = &hashes[i];
+    let upgraded = match credential {
+        Credential::RecoveryCode => None,
+        _ if !needs_rehash(matched, &cfg.argon2) => None,
+        _ => match hash_with(&attempt, &cfg.argon2) {
+            Ok(new) => Some(new),
+            // Failure only costs the upgrade.
+            Err(e) => {
+                eprintln!("[FocusBlocker] Cannot upgrade password hash: {e}");
+                None
+            }
+        },
+    };
+    if credential != Credential::RecoveryCode && upgraded.is_none() && failed.count == 0 {
+        return Ok(Verdict::Accepted);
+    }
+
+    let mut verdict = Verdict::Accepted;
+    config::update(|cfg| {
+        if credential == Credential::RecoveryCode {
+            // Another process may have used the same code meanwhile.
+            let Some(j) = cfg.recovery_codes.iter().position(|h| h == matched) else {
+                verdict = Verdict::Rejected {
+                    retry_after_ms: None,
+                };
+                return;
+            };
+            cfg.recovery_codes.remove(j);
+        }
+        *counter_mut(&mut cfg.failed_attempts, credential) = AttemptCount::default();
+        if let Some(new) = upgraded {
+            replace_hash(cfg, matched, new);
+        }
+    })?;
+    Ok(verdict)
+}
+
+/// The hashes an attempt at `credential` may match.
+fn stored(cfg: &Config, credential: Credential) -> Vec<String> {
+    match credential {
+        Credential::Admin => cfg.password_hash.iter().cloned().collect(),
+        Credential::SessionPin if cfg.session_pins.is_empty() => {
+            cfg.password_hash.iter().cloned().collect()
+        }
+        Credential::SessionPin => cfg.session_pins.iter().map(|p| p.hash.clone()).collect(),
+        Credential::RecoveryCode => cfg.recovery_codes.clone(),
+    }
+}
+
+fn counter(attempts: &FailedAttempts, credential: Credential) -> &AttemptCount {
+    match credential {
+        Credential::Admin => &attempts.admin,
+        Credential::SessionPin => &attempts.session_pin,
+        Credential::RecoveryCode => &attempts.recovery_code,
+    }
+}
+
+fn counter_mut(attempts: &mut FailedAttempts, credential: Credential) -> &mut AttemptCount {
+    match credential {
+        Credential::Admin => &mut attempts.admin,
+        Credential::SessionPin => &mut attempts.session_pin,
+        Credential::RecoveryCode => &mut attempts.recovery_code,
+    }
+}
+
+/// Put `new` where the admin password or a PIN hash was `old`. Nothing
+/// happens if it was changed in the meantime.
+fn replace_hash(cfg: &mut Config, old: &str, new: String) {
+    let slot = cfg
+        .password_hash
+        .iter_mut()
+        .chain(cfg.session_pins.iter_mut().map(|p| &mut p.hash))
+        .find(|h| *h == old);
+    if let Some(slot) = slot {
+        *slot = new;
+        eprintln!("[FocusBlocker] Upgraded a password hash to the current Argon2 policy.");
+    }
+}
+
+/// True if `attempt` verifies against any of `hashes`.
+pub fn matches_any(attempt: &str, hashes: &[String]) -> Result<bool, AppError> {
+    position(attempt, hashes).map(|found| found.is_some())
+}
+
+fn position(attempt: &str, hashes: &[String]) -> Result<Option<usize>, AppError> {
+    for (i, hash) in hashes.iter().enumerate() {
+        if verify(attempt, hash)? {
+            return Ok(Some(i));
+        }
+    }
+    Ok(None)
+}
+
+/// A fresh set of recovery codes, formatted `xxxx-xxxx-xxxx`, with their
+/// hashes for the config.
+pub fn recovery_codes(policy: &Argon2Policy) -> Result<(Vec<String>, Vec<String>), AppError> {
+    let mut codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    let mut hashes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let chars: Vec<u8> = (0..12)
+            .map(|_| {
+                let i = OsRng.next_u32() as usize % RECOVERY_ALPHABET.len();
+                RECOVERY_ALPHABET[i]
+            })
+            .collect();
+        let code: Vec<&str> = chars
+            .chunks(4)
+            .map(|c| std::str::from_utf8(c).unwrap_or_default())
+            .collect();
+        let code = code.join("-");
+        hashes.push(hash_with(&normalize_recovery_code(&code), policy)?);
+        codes.push(code);
+    }
+    Ok((codes, hashes))
+}
+
+/// Codes are compared without dashes, spaces or case.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Why `secret` is too weak to become an admin password
+/// (`Credential::Admin`) or a session PIN, or `None` if it will do.
+pub fn weakness(secret: &str, credential: Credential) -> Option<String> {
+    let chars: Vec<char> = secret.chars().collect();
+    let (what, min) = match credential {
+        Credential::Admin => ("Password", MIN_PASSWORD_LEN),
+        _ => ("PIN", MIN_PIN_LEN),
+    };
+
+    if chars.len() < min {
+        return Some(format!("{what} must be at least {min} characters."));
+    }
+    if chars.iter().all(|&c| c == chars[0]) {
+        return Some(format!("{what} cannot be one repeated character."));
+    }
+    // 1234, 9876, abcd...
+    let steps: Vec<i64> = chars
+        .windows(2)
+        .map(|w| w[1] as i64 - w[0] as i64)
+        .collect();
+    if steps.iter().all(|&s| s == steps[0]) && steps[0].abs() == 1 {
+        return Some(format!("{what} cannot be a straight run like 1234."));
+    }
+    if credential == Credential::Admin && chars.iter().all(char::is_ascii_digit) {
+        return Some("Password cannot be only digits; use a PIN for that.".into());
+    }
+    None
+}
+
+/// When the next attempt is allowed, or `None` if it needn't wait.
+fn retry_at(attempts: &AttemptCount) -> Option<u64> {
+    let last = attempts.last_at?;
+    match attempts.count {
+        n if n < FREE_ATTEMPTS => None,
+        n if n >= LOCKOUT_AFTER => Some(last + LOCKOUT_MS),
+        // 1 s after the last free try, doubling up to about a minute.
+        n => Some(last + (1000 << (n - FREE_ATTEMPTS))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::sandbox::Sandbox;
+    use std::fs::{self, File};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn backoff_grows_then_locks_out() {
+        let after = |count| {
+            retry_at(&AttemptCount {
+                count,
+                last_at: Some(0),
+            })
+        };
+
+        assert_eq!(after(0), None);
+        assert_eq!(after(FREE_ATTEMPTS - 1), None);
+        assert_eq!(after(FREE_ATTEMPTS), Some(1000));
+        assert_eq!(after(FREE_ATTEMPTS + 1), Some(2000));
+        assert_eq!(after(LOCKOUT_AFTER - 1), Some(64_000));
+        assert_eq!(after(LOCKOUT_AFTER), Some(LOCKOUT_MS));
+        assert_eq!(after(LOCKOUT_AFTER + 5), Some(LOCKOUT_MS));
+    }
+
+    /// Cheap enough to keep debug-build tests fast.
+    fn test_policy() -> Argon2Policy {
+        Argon2Policy {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+            target_ms: 0,
+        }
+    }
+
+    /// Admin password "correct horse", PIN "2580" and one recovery code.
+    fn with_credentials() -> String {
+        let policy = test_policy();
+        let (codes, code_hashes) = recovery_codes(&policy).unwrap();
+        config::update(|cfg| {
+            cfg.password_hash = Some(hash_with("correct horse", &policy).unwrap());
+            cfg.session_pins = vec![config::SessionPin {
+                label: "kids".into(),
+                hash: hash_with("2580", &policy).unwrap(),
+            }];
+            cfg.recovery_codes = code_hashes[..1].to_vec();
+            cfg.argon2 = policy;
+        })
+        .unwrap();
+        codes[0].clone()
+    }
+
+    fn failures() -> FailedAttempts {
+        config::load().unwrap().failed_attempts
+    }
+
+    #[test]
+    fn credentials_are_counted_separately() {
+        let _sandbox = Sandbox::new();
+        with_credentials();
+
+        check("wrong", Credential::Admin).unwrap();
+        assert_eq!(failures().admin.count, 1);
+        assert_eq!(failures().session_pin.count, 0);
+
+        config::update(|cfg| {
+            cfg.failed_attempts.admin = AttemptCount {
+                count: LOCKOUT_AFTER,
+                last_at: Some(config::now_ms()),
+            };
+        })
+        .unwrap();
+        assert!(matches!(
+            check("correct horse", Credential::Admin).unwrap(),
+            Verdict::Throttled { .. }
+        ));
+
+        // A correct PIN doesn't lift the admin password's backoff.
+        assert_eq!(
+            check("2580", Credential::SessionPin).unwrap(),
+            Verdict::Accepted
+        );
+        assert_eq!(failures().admin.count, LOCKOUT_AFTER);
+        assert!(matches!(
+            check("correct horse", Credential::Admin).unwrap(),
+            Verdict::Throttled { .. }
+        ));
+    }
+
+    #[test]
+    fn success_without_changes_does_not_write() {
+        let sandbox = Sandbox::new();
+        with_credentials();
+        let path = sandbox.root.join(".focusblocker/config.json");
+        let before = fs::read_to_string(&path).unwrap();
+        let a_while_ago = SystemTime::now() - Duration::from_secs(3600);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(a_while_ago)
+            .unwrap();
+
+        assert_eq!(
+            check("correct horse", Credential::Admin).unwrap(),
+            Verdict::Accepted
+        );
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        assert_eq!(modified, a_while_ago);
+
+        // A failure is recorded, and the next success clears it.
+        check("wrong", Credential::Admin).unwrap();
+        assert_eq!(failures().admin.count, 1);
+        check("correct horse", Credential::Admin).unwrap();
+        assert_eq!(failures().admin.count, 0);
+        assert_eq!(fs::read_to_string(&path).unwrap(), before);
+    }
+
+    #[test]
+    fn recovery_code_works_once() {
+        let _sandbox = Sandbox::new();
+        let code = with_credentials();
+
+        assert_eq!(
+            check(&code, Credential::RecoveryCode).unwrap(),
+            Verdict::Accepted
+        );
+        assert!(config::load().unwrap().recovery_codes.is_empty());
+        assert_eq!(
+            check(&code, Credential::RecoveryCode).unwrap(),
+            Verdict::Rejected {
+                retry_after_ms: None
+            }
+        );
+    }
+
+    #[test]
+    fn recovery_codes_match_loosely() {
+        let (codes, hashes) = recovery_codes(&test_policy()).unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(codes[0].len(), 14);
+
+        let typed = format!(" {} ", codes[3].replace('-', "").to_uppercase());
+        let typed = normalize_recovery_code(&typed);
+        assert_eq!(position(&typed, &hashes).unwrap(), Some(3));
+        assert_eq!(position("nope", &hashes).unwrap(), None);
+    }
+
+    #[test]
+    fn cheaper_hashes_need_rehash() {
+        let cheap = test_policy();
+        let policy = Argon2Policy {
+            iterations: 2,
+            ..cheap.clone()
+        };
+
+        let old = hash_with("secret", &cheap).unwrap();
+        assert!(needs_rehash(&old, &policy));
+        assert!(!needs_rehash(&old, &cheap));
+
+        let new = hash_with("secret", &policy).unwrap();
+        assert!(!needs_rehash(&new, &policy));
+        assert!(verify("secret", &new).unwrap());
+    }
+
+    #[test]
+    fn check_upgrades_cheaper_hashes() {
+        let _sandbox = Sandbox::new();
+        with_credentials();
+        let stronger = Argon2Policy {
+            iterations: 2,
+            ..test_policy()
+        };
+        config::update(|cfg| cfg.argon2 = stronger.clone()).unwrap();
+        let old = config::load().unwrap().password_hash.unwrap();
+        assert!(needs_rehash(&old, &stronger));
+
+        assert_eq!(
+            check("correct horse", Credential::Admin).unwrap(),
+            Verdict::Accepted
+        );
+        let new = config::load().unwrap().password_hash.unwrap();
+        assert_ne!(new, old);
+        assert!(!needs_rehash(&new, &stronger));
+        assert!(verify("correct horse", &new).unwrap());
+
+        // The PIN keeps its old hash until it is used.
+        let pin = &config::load().unwrap().session_pins[0].hash;
+        assert!(needs_rehash(pin, &stronger));
+    }
+
+    #[test]
+    fn weak_secrets_are_refused() {
+        let weak = |s| weakness(s, Credential::Admin).is_some();
+        assert!(weak("short"));
+        assert!(weak("aaaaaaaaaa"));
+        assert!(weak("abcdefghij"));
+        assert!(weak("20240101"));
+        assert!(!weak("correct horse"));
+
+        let weak_pin = |s| weakness(s, Credential::SessionPin).is_some();
+        assert!(weak_pin("123"));
+        assert!(weak_pin("1111"));
+        assert!(weak_pin("4321"));
+        assert!(!weak_pin("2580"));
+    }
+}
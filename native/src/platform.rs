@@ -1,9 +1,17 @@
 //! OS-specific paths and utilities.
 
-use std::path::PathBuf;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 /// Return the system hosts file path.
 pub fn hosts_file_path() -> PathBuf {
+    #[cfg(test)]
+    if let Some(root) = sandbox::root() {
+        return root.join("hosts");
+    }
     if cfg!(target_os = "windows") {
         let root = std::env::var("SystemRoot").unwrap_or_else(|_| r"C:\Windows".into());
         PathBuf::from(root).join(r"System32\drivers\etc\hosts")
@@ -16,6 +24,10 @@ pub fn hosts_file_path() -> PathBuf {
 ///   macOS / Linux: ~/.focusblocker/
 ///   Windows:       %APPDATA%\FocusBlocker\
 pub fn config_dir() -> PathBuf {
+    #[cfg(test)]
+    if let Some(root) = sandbox::root() {
+        return root.join(".focusblocker");
+    }
     if cfg!(target_os = "windows") {
         directories::BaseDirs::new()
             .map(|b| b.config_dir().join("FocusBlocker"))
@@ -27,18 +39,332 @@ pub fn config_dir() -> PathBuf {
     }
 }
 
-/// Flush the OS DNS cache so hosts-file changes take effect immediately.
-pub fn flush_dns() {
-    if cfg!(target_os = "windows") {
-        let _ = std::process::Command::new("ipconfig")
-            .arg("/flushdns")
-            .output();
-    } else if cfg!(target_os = "macos") {
-        let _ = std::process::Command::new("dscacheutil")
-            .arg("-flushcache")
-            .output();
-        let _ = std::process::Command::new("killall")
-            .args(["-HUP", "mDNSResponder"])
-            .output();
+/// Write to a temp file next to `path`, fsync it, then rename it over
+/// `path`, so readers see either the old or the new content. An existing
+/// file's permissions are kept.
+pub fn write_atomic(path: &Path, data: &str) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".tmp.{}", std::process::id()));
+    let tmp = PathBuf::from(tmp);
+
+    if let Err(e) = write_synced(&tmp, data, path).and_then(|()| fs::rename(&tmp, path)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+
+    // Persist the rename itself (directory entry) on Unix.
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Create `tmp` holding `data`, with `original`'s permissions if it exists.
+fn write_synced(tmp: &Path, data: &str, original: &Path) -> io::Result<()> {
+    let mut file = File::create(tmp)?;
+    file.write_all(data.as_bytes())?;
+    if let Ok(meta) = fs::metadata(original) {
+        file.set_permissions(meta.permissions())?;
+    }
+    file.sync_all()
+}
+
+/// Outcome of flushing one DNS cache.
+#[derive(Serialize, Debug, Clone)]
+pub struct DnsFlush {
+    pub resolver: &'static str,
+    #[serde(flatten)]
+    pub status: FlushStatus,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "status", content = "error", rename_all = "camelCase")]
+pub enum FlushStatus {
+    Flushed,
+    /// The cache isn't installed or running on this machine.
+    NotPresent,
+    Failed(String),
+}
+
+/// Flush the OS DNS cache(s) so hosts-file changes take effect immediately.
+/// Returns one outcome per cache that was considered.
+pub fn flush_dns() -> Vec<DnsFlush> {
+    #[cfg(test)]
+    if sandbox::root().is_some() {
+        return Vec::new();
+    }
+    flush_plan(std::env::consts::OS, &Detected::probe())
+        .into_iter()
+        .map(|flush| DnsFlush {
+            resolver: flush.resolver,
+            status: flush.execute(),
+        })
+        .collect()
+}
+
+/// Which Linux caches are running, as far as flushing is concerned.
+#[derive(Debug, Default)]
+struct Detected {
+    /// systemd-resolved (Ubuntu, Fedora, ...): its runtime dir exists.
+    systemd_resolved: bool,
+    /// nscd: its socket exists.
+    nscd: bool,
+    dnsmasq_pids: Vec<String>,
+}
+
+impl Detected {
+    fn probe() -> Self {
+        Self {
+            systemd_resolved: Path::new("/run/systemd/resolve").exists(),
+            nscd: ["/run/nscd/socket", "/var/run/nscd/socket"]
+                .iter()
+                .any(|p| Path::new(p).exists()),
+            dnsmasq_pids: find_processes("dnsmasq"),
+        }
+    }
+}
+
+/// One cache and how to flush it.
+#[derive(Debug, PartialEq)]
+struct Flush {
+    resolver: &'static str,
+    /// Commands tried in order, moving on only while the binary is missing.
+    /// Empty when the cache isn't present.
+    commands: Vec<Vec<String>>,
+}
+
+impl Flush {
+    fn new(resolver: &'static str, present: bool, commands: &[&[&str]]) -> Self {
+        let commands = if present {
+            commands
+                .iter()
+                .map(|c| c.iter().map(|s| s.to_string()).collect())
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Self { resolver, commands }
+    }
+
+    fn execute(&self) -> FlushStatus {
+        if self.commands.is_empty() {
+            return FlushStatus::NotPresent;
+        }
+        self.commands
+            .iter()
+            .map(|c| run(&c[0], &c[1..]))
+            .find(|status| !matches!(status, FlushStatus::NotPresent))
+            .unwrap_or_else(|| {
+                let names: Vec<&str> = self.commands.iter().map(|c| c[0].as_str()).collect();
+                FlushStatus::Failed(format!("{} not found", names.join(" / ")))
+            })
+    }
+}
+
+/// The caches to flush on `os` (as in `std::env::consts::OS`).
+fn flush_plan(os: &str, found: &Detected) -> Vec<Flush> {
+    match os {
+        "windows" => vec![Flush::new("dnscache", true, &[&["ipconfig", "/flushdns"]])],
+        "macos" => vec![
+            Flush::new("dscacheutil", true, &[&["dscacheutil", "-flushcache"]]),
+            Flush::new(
+                "mDNSResponder",
+                true,
+                &[&["killall", "-HUP", "mDNSResponder"]],
+            ),
+        ],
+        _ => {
+            // SIGHUP makes dnsmasq clear its cache and re-read /etc/hosts.
+            let mut hup = vec!["kill".to_string(), "-HUP".to_string()];
+            hup.extend(found.dnsmasq_pids.iter().cloned());
+            vec![
+                Flush::new(
+                    "systemd-resolved",
+                    found.systemd_resolved,
+                    // Older systemd only ships the legacy tool.
+                    &[
+                        &["resolvectl", "flush-caches"],
+                        &["systemd-resolve", "--flush-caches"],
+                    ],
+                ),
+                // Only the hosts table is invalidated.
+                Flush::new("nscd", found.nscd, &[&["nscd", "-i", "hosts"]]),
+                Flush {
+                    resolver: "dnsmasq",
+                    commands: if found.dnsmasq_pids.is_empty() {
+                        Vec::new()
+                    } else {
+                        vec![hup]
+                    },
+                },
+            ]
+        }
+    }
+}
+
+/// PIDs of running processes with the given name (Linux `/proc` scan).
+fn find_processes(name: &str) -> Vec<String> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let pid = e.file_name().into_string().ok()?;
+            if !pid.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let comm = fs::read_to_string(e.path().join("comm")).ok()?;
+            (comm.trim() == name).then_some(pid)
+        })
+        .collect()
+}
+
+/// Run a command, mapping "binary not found" to `NotPresent`.
+fn run(program: &str, args: &[String]) -> FlushStatus {
+    match Command::new(program).args(args).output() {
+        Ok(out) if out.status.success() => FlushStatus::Flushed,
+        Ok(out) => {
+            let stderr = String::from_utf8_lossy(&out.stderr).trim().to_string();
+            FlushStatus::Failed(if stderr.is_empty() {
+                format!("{program} exited with {}", out.status)
+            } else {
+                stderr
+            })
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => FlushStatus::NotPresent,
+        Err(e) => FlushStatus::Failed(format!("{program}: {e}")),
+    }
+}
+
+/// Per-thread stand-in for the machine, so tests that go through the
+/// config and hosts file each get their own and never touch the real ones.
+#[cfg(test)]
+pub mod sandbox {
+    use std::cell::RefCell;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    thread_local! {
+        static ROOT: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+    }
+
+    pub(super) fn root() -> Option<PathBuf> {
+        ROOT.with(|r| r.borrow().clone())
+    }
+
+    /// Redirects `config_dir` and `hosts_file_path` into a fresh temp
+    /// directory on this thread, and skips DNS flushes, until dropped.
+    pub struct Sandbox {
+        pub root: PathBuf,
+    }
+
+    impl Sandbox {
+        pub fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let root = std::env::temp_dir().join(format!(
+                "focusblocker-test-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            fs::write(root.join("hosts"), "127.0.0.1 localhost\n").unwrap();
+            ROOT.with(|r| *r.borrow_mut() = Some(root.clone()));
+            Self { root }
+        }
+    }
+
+    impl Drop for Sandbox {
+        fn drop(&mut self) {
+            ROOT.with(|r| *r.borrow_mut() = None);
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(plan: &[Flush], resolver: &str) -> Vec<String> {
+        let flush = plan.iter().find(|f| f.resolver == resolver).unwrap();
+        flush.commands.iter().map(|c| c.join(" ")).collect()
+    }
+
+    #[test]
+    fn linux_flushes_only_what_is_running() {
+        let plan = flush_plan("linux", &Detected::default());
+        let resolvers: Vec<&str> = plan.iter().map(|f| f.resolver).collect();
+        assert_eq!(resolvers, ["systemd-resolved", "nscd", "dnsmasq"]);
+        assert!(plan.iter().all(|f| f.commands.is_empty()));
+        assert!(plan
+            .iter()
+            .all(|f| matches!(f.execute(), FlushStatus::NotPresent)));
+    }
+
+    #[test]
+    fn linux_commands_per_resolver() {
+        let found = Detected {
+            systemd_resolved: true,
+            nscd: true,
+            dnsmasq_pids: vec!["41".into(), "97".into()],
+        };
+        let plan = flush_plan("linux", &found);
+        assert_eq!(
+            commands(&plan, "systemd-resolved"),
+            ["resolvectl flush-caches", "systemd-resolve --flush-caches"]
+        );
+        assert_eq!(commands(&plan, "nscd"), ["nscd -i hosts"]);
+        assert_eq!(commands(&plan, "dnsmasq"), ["kill -HUP 41 97"]);
+    }
+
+    #[test]
+    fn other_platforms_use_their_own_tools() {
+        let found = Detected::default();
+        let windows = flush_plan("windows", &found);
+        assert_eq!(commands(&windows, "dnscache"), ["ipconfig /flushdns"]);
+        let macos = flush_plan("macos", &found);
+        assert_eq!(commands(&macos, "dscacheutil"), ["dscacheutil -flushcache"]);
+        assert_eq!(
+            commands(&macos, "mDNSResponder"),
+            ["killall -HUP mDNSResponder"]
+        );
+    }
+
+    #[test]
+    fn missing_binaries_are_reported() {
+        assert!(matches!(
+            run("focusblocker-no-such-tool", &[]),
+            FlushStatus::NotPresent
+        ));
+        // Detected but none of its tools installed is a failure, not absence.
+        let flush = Flush::new(
+            "test",
+            true,
+            &[
+                &["focusblocker-no-such-tool"],
+                &["focusblocker-no-such-either"],
+            ],
+        );
+        match flush.execute() {
+            FlushStatus::Failed(e) => assert_eq!(
+                e,
+                "focusblocker-no-such-tool / focusblocker-no-such-either not found"
+            ),
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn falls_back_past_missing_binaries() {
+        let flush = Flush::new("test", true, &[&["focusblocker-no-such-tool"], &["true"]]);
+        assert!(matches!(flush.execute(), FlushStatus::Flushed));
+        let flush = Flush::new("test", true, &[&["false"]]);
+        assert!(matches!(flush.execute(), FlushStatus::Failed(e) if e.contains("exited with")));
     }
 }
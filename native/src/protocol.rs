@@ -0,0 +1,829 @@
+//! Typed native-messaging protocol.
+//!
+//! Every message from the extension is a JSON object tagged by `type`.
+//! Requests are parsed strictly: unknown message types, unknown fields and
+//! wrongly-typed values are rejected with an `INVALID_MESSAGE` error instead
+//! of silently falling back to defaults.
+//!
+//! Responses are tagged by `status` ("OK" | "ERROR"). Errors always carry a
+//! machine-readable `code` alongside the human-readable `message`.
+
+use crate::config::{self, EarlyEnd, Mode, Phase};
+use crate::history::{SessionRecord, Stats};
+use crate::platform::DnsFlush;
+use crate::schedule::{ClockTime, Day, Schedule};
+use chrono::NaiveDate;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+// =========================================================================
+// Requests (extension → host)
+// =========================================================================
+
+#[derive(Deserialize, Debug)]
+#[serde(
+    tag = "type",
+    rename_all = "SCREAMING_SNAKE_CASE",
+    rename_all_fields = "camelCase",
+    deny_unknown_fields
+)]
+pub enum Request {
+    // Variants without fields are written `Ping {}` rather than `Ping`:
+    // serde ignores `deny_unknown_fields` on unit variants.
+    Ping {},
+
+    // ---- Cross-profile state sync ----
+    GetState {},
+    /// Stop reporting `configRecovered` in GET_STATE once it has been seen.
+    DismissRecovery {},
+    /// Focus time per day and per week from the session history.
+    GetStats {
+        #[serde(default)]
+        days: Option<u32>,
+        #[serde(default)]
+        weeks: Option<u32>,
+    },
+    /// Ended sessions that started between the two local dates, inclusive.
+    GetHistory {
+        #[serde(default)]
+        from: Option<NaiveDate>,
+        #[serde(default)]
+        to: Option<NaiveDate>,
+    },
+    StartSession {
+        #[serde(default)]
+        duration_minutes: Option<u32>,
+        #[serde(default)]
+        scheduled_id: Option<String>,
+        #[serde(default)]
+        locked: bool,
+        #[serde(default)]
+        mode: Option<Mode>,
+        /// Alternate work and break phases instead of one work span.
+        #[serde(default)]
+        cycle: Option<CyclePayload>,
+    },
+    /// `confirmation` and `password` answer the early-end friction policy.
+    EndSession {
+        #[serde(default)]
+        natural: bool,
+        #[serde(default)]
+        parent_pin: Option<String>,
+        #[serde(default)]
+        confirmation: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+    },
+    /// Push the end time back; the start time is kept.
+    ExtendSession {
+        minutes: u32,
+    },
+    /// Stop the session clock; strict-mode hosts entries are lifted.
+    PauseSession {
+        #[serde(default)]
+        parent_pin: Option<String>,
+    },
+    ResumeSession {},
+    /// Strict to precision lifts the hosts block, so it needs the same PIN
+    /// and early-end answers as END_SESSION.
+    SwitchMode {
+        mode: Mode,
+        #[serde(default)]
+        parent_pin: Option<String>,
+        #[serde(default)]
+        confirmation: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+    },
+    SyncRules {
+        #[serde(default)]
+        youtube_rules: Option<YoutubeRulesPayload>,
+        #[serde(default)]
+        blocked_sites: Option<Vec<String>>,
+        #[serde(default)]
+        domain_families: Option<BTreeMap<String, Vec<String>>>,
+    },
+    /// `password` is the admin password, required once one is set.
+    SyncSettings {
+        settings: SettingsPayload,
+        #[serde(default)]
+        password: Option<String>,
+    },
+    /// Replaces the whole schedule list.
+    SyncSchedules {
+        schedules: Vec<SchedulePayload>,
+    },
+
+    // ---- Registry management (Windows) ----
+    RegisterExtension {
+        extension_id: String,
+        #[serde(default)]
+        manifest_path: Option<String>,
+    },
+
+    // ---- Legacy per-domain controls (compat for old extension) ----
+    BlockDomain {
+        domain: String,
+    },
+    UnblockDomain {
+        domain: String,
+    },
+    Quit {
+        #[serde(default)]
+        password: Option<String>,
+    },
+
+    // ---- Credentials ----
+    /// Set the admin password; `current_password` is required to replace
+    /// an existing one.
+    ChangePassword {
+        #[serde(default)]
+        current_password: Option<String>,
+        new_password: String,
+    },
+    /// Add a session PIN, or replace the one with the same label.
+    SetPin {
+        #[serde(default)]
+        password: Option<String>,
+        label: String,
+        pin: String,
+    },
+    RemovePin {
+        #[serde(default)]
+        password: Option<String>,
+        label: String,
+    },
+    /// Replace a forgotten admin password, spending one recovery code.
+    ResetPassword {
+        recovery_code: String,
+        new_password: String,
+    },
+    /// Replace all recovery codes with a fresh set.
+    RegenerateRecoveryCodes {
+        #[serde(default)]
+        password: Option<String>,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct YoutubeRulesPayload {
+    #[serde(default)]
+    pub blocked_channels: Vec<String>,
+    #[serde(default)]
+    pub allowed_channels: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CyclePayload {
+    pub work_minutes: u32,
+    pub break_minutes: u32,
+    #[serde(default)]
+    pub long_break_minutes: u32,
+    #[serde(default)]
+    pub long_break_every: u32,
+}
+
+/// Partial settings update — only the fields present are written.
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SettingsPayload {
+    #[serde(default)]
+    pub default_mode: Option<Mode>,
+    #[serde(default)]
+    pub block_all_channels: Option<bool>,
+    #[serde(default)]
+    pub session_duration_minutes: Option<u32>,
+    #[serde(default)]
+    pub sink_addresses: Option<Vec<IpAddr>>,
+    #[serde(default)]
+    pub tamper_policy: Option<TamperPolicyPayload>,
+    #[serde(default)]
+    pub calendar_import: Option<CalendarImportPayload>,
+    #[serde(default)]
+    pub pause_policy: Option<PausePolicyPayload>,
+    #[serde(default)]
+    pub early_end: Option<EarlyEnd>,
+    /// `null` removes the limit; leaving the key out keeps it as it is.
+    #[serde(default, deserialize_with = "present")]
+    pub daily_session_limit: Option<Option<u32>>,
+}
+
+/// Tell an explicit `null` (`Some(None)`) apart from a missing key (`None`).
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PausePolicyPayload {
+    #[serde(default)]
+    pub max_pauses: Option<u32>,
+    #[serde(default)]
+    pub max_pause_minutes: Option<u32>,
+}
+
+impl From<PausePolicyPayload> for config::PausePolicy {
+    fn from(p: PausePolicyPayload) -> Self {
+        Self {
+            max_pauses: p.max_pauses,
+            max_pause_minutes: p.max_pause_minutes,
+        }
+    }
+}
+
+/// Partial update of the calendar import patterns.
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CalendarImportPayload {
+    #[serde(default)]
+    pub strict_pattern: Option<String>,
+    #[serde(default)]
+    pub precision_pattern: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TamperPolicyPayload {
+    #[serde(default)]
+    pub extend_minutes: u32,
+    #[serde(default)]
+    pub lock_after: Option<u32>,
+}
+
+impl From<TamperPolicyPayload> for config::TamperPolicy {
+    fn from(p: TamperPolicyPayload) -> Self {
+        Self {
+            extend_minutes: p.extend_minutes,
+            lock_after: p.lock_after,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SchedulePayload {
+    pub id: String,
+    pub days: Vec<Day>,
+    pub start_time: ClockTime,
+    pub end_time: ClockTime,
+    pub mode: Mode,
+    #[serde(default)]
+    pub locked: bool,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub starts_on: Option<NaiveDate>,
+    #[serde(default)]
+    pub ends_on: Option<NaiveDate>,
+    #[serde(default = "default_interval")]
+    pub interval_weeks: u32,
+    #[serde(default)]
+    pub except: Vec<NaiveDate>,
+    /// Set on schedules imported from a calendar file.
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+fn default_interval() -> u32 {
+    1
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl From<SchedulePayload> for Schedule {
+    fn from(p: SchedulePayload) -> Self {
+        Self {
+            id: p.id,
+            days: p.days,
+            start_time: p.start_time,
+            end_time: p.end_time,
+            mode: p.mode,
+            locked: p.locked,
+            enabled: p.enabled,
+            starts_on: p.starts_on,
+            ends_on: p.ends_on,
+            interval_weeks: p.interval_weeks,
+            except: p.except,
+            source: p.source,
+            last_run: None,
+        }
+    }
+}
+
+/// The run marker is dropped; `SYNC_SCHEDULES` carries it over by id.
+impl From<Schedule> for SchedulePayload {
+    fn from(s: Schedule) -> Self {
+        Self {
+            id: s.id,
+            days: s.days,
+            start_time: s.start_time,
+            end_time: s.end_time,
+            mode: s.mode,
+            locked: s.locked,
+            enabled: s.enabled,
+            starts_on: s.starts_on,
+            ends_on: s.ends_on,
+            interval_weeks: s.interval_weeks,
+            except: s.except,
+            source: s.source,
+        }
+    }
+}
+
+impl Request {
+    /// Parse a raw JSON message into a typed request.
+    pub fn from_value(msg: serde_json::Value) -> Result<Self, ErrorReply> {
+        if !msg.is_object() {
+            return Err(ErrorReply::new(
+                ErrorCode::InvalidMessage,
+                "Message must be a JSON object",
+            ));
+        }
+        if msg.get("type").and_then(|t| t.as_str()).is_none() {
+            return Err(ErrorReply::new(
+                ErrorCode::InvalidMessage,
+                "Missing 'type' field",
+            ));
+        }
+
+        serde_json::from_value(msg)
+            .map_err(|e| ErrorReply::new(ErrorCode::InvalidMessage, e.to_string()))
+    }
+}
+
+// =========================================================================
+// Responses (host → extension)
+// =========================================================================
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "status")]
+pub enum Response {
+    #[serde(rename = "OK")]
+    Ok(Reply),
+    #[serde(rename = "ERROR")]
+    Error(ErrorReply),
+}
+
+impl Response {
+    /// Plain `{"status": "OK"}` acknowledgement.
+    pub fn ack() -> Self {
+        Response::Ok(Reply::Ack)
+    }
+
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        Response::Error(ErrorReply::new(code, message))
+    }
+}
+
+/// Success payloads. Fields are flattened next to `status`.
+#[derive(Serialize, Debug)]
+#[serde(untagged, rename_all_fields = "camelCase")]
+pub enum Reply {
+    Ack,
+    State(Box<StateReply>),
+    /// `dns_flush` lists per-resolver outcomes when the hosts file changed.
+    Session {
+        session: SessionView,
+        dns_flush: Vec<DnsFlush>,
+    },
+    Ended {
+        natural: bool,
+    },
+    Stats {
+        stats: Stats,
+    },
+    History {
+        sessions: Vec<SessionRecord>,
+    },
+    /// Plaintext codes; this is the only time they are shown.
+    RecoveryCodes {
+        recovery_codes: Vec<String>,
+    },
+    Mode {
+        mode: Mode,
+        dns_flush: Vec<DnsFlush>,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StateReply {
+    pub session: Option<SessionView>,
+    pub youtube_rules: Option<YoutubeRulesView>,
+    pub blocked_domains: Vec<String>,
+    pub domain_families: BTreeMap<String, Vec<String>>,
+    /// Every hostname currently sinkholed in the hosts file.
+    pub blocked_hosts: Vec<String>,
+    pub settings: Option<SettingsView>,
+    pub schedules: Vec<ScheduleView>,
+    pub daily_sessions: DailySessionsView,
+    /// True once an admin password has been set.
+    pub password_set: bool,
+    /// Labels of the session PINs.
+    pub session_pins: Vec<String>,
+    /// Unused recovery codes.
+    pub recovery_codes_left: usize,
+    /// Set if `config.json` was unreadable and a backup was used instead.
+    pub config_recovered: Option<RecoveryView>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryView {
+    pub at: u64,
+    pub backup: String,
+    pub reason: String,
+}
+
+impl From<&config::Recovery> for RecoveryView {
+    fn from(r: &config::Recovery) -> Self {
+        Self {
+            at: r.at,
+            backup: r.backup.clone(),
+            reason: r.reason.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DailySessionsView {
+    /// Sessions started today (local date).
+    pub used: u32,
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionView {
+    pub mode: Mode,
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+    pub locked: bool,
+    pub scheduled_id: Option<String>,
+    pub id: Option<String>,
+    pub tamper_count: u32,
+    pub paused_remaining_ms: Option<u64>,
+    pub paused_at: Option<u64>,
+    pub pause_count: u32,
+    pub end_requested_at: Option<u64>,
+    pub cycle: Option<CycleView>,
+}
+
+impl From<&config::SessionState> for SessionView {
+    fn from(s: &config::SessionState) -> Self {
+        Self {
+            mode: s.mode,
+            start_time: s.start_time,
+            end_time: s.end_time,
+            locked: s.locked,
+            scheduled_id: s.scheduled_id.clone(),
+            id: s.id.clone(),
+            tamper_count: s.tamper_count,
+            paused_remaining_ms: s.paused_remaining_ms,
+            paused_at: s.paused_at,
+            pause_count: s.pause_count,
+            end_requested_at: s.end_requested_at,
+            cycle: s.cycle.as_ref().map(|c| CycleView {
+                work_minutes: c.work_minutes,
+                break_minutes: c.break_minutes,
+                long_break_minutes: c.long_break_minutes,
+                long_break_every: c.long_break_every,
+                phase: c.phase,
+                phase_end: c.phase_end,
+                // Frozen at the moment of pausing.
+                phase_remaining_ms: c
+                    .phase_end
+                    .saturating_sub(s.paused_at.unwrap_or_else(config::now_ms)),
+                completed: c.completed,
+            }),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CycleView {
+    pub work_minutes: u32,
+    pub break_minutes: u32,
+    pub long_break_minutes: u32,
+    pub long_break_every: u32,
+    pub phase: Phase,
+    pub phase_end: u64,
+    pub phase_remaining_ms: u64,
+    pub completed: u32,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleView {
+    pub id: String,
+    pub days: Vec<Day>,
+    pub start_time: ClockTime,
+    pub end_time: ClockTime,
+    pub mode: Mode,
+    pub locked: bool,
+    pub enabled: bool,
+    pub starts_on: Option<NaiveDate>,
+    pub ends_on: Option<NaiveDate>,
+    pub interval_weeks: u32,
+    pub except: Vec<NaiveDate>,
+    pub source: Option<String>,
+    pub last_run: Option<u64>,
+}
+
+impl From<&Schedule> for ScheduleView {
+    fn from(s: &Schedule) -> Self {
+        Self {
+            id: s.id.clone(),
+            days: s.days.clone(),
+            start_time: s.start_time,
+            end_time: s.end_time,
+            mode: s.mode,
+            locked: s.locked,
+            enabled: s.enabled,
+            starts_on: s.starts_on,
+            ends_on: s.ends_on,
+            interval_weeks: s.interval_weeks,
+            except: s.except.clone(),
+            source: s.source.clone(),
+            last_run: s.last_run,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct YoutubeRulesView {
+    pub blocked_channels: Vec<String>,
+    pub allowed_channels: Vec<String>,
+}
+
+impl From<&config::YoutubeRules> for YoutubeRulesView {
+    fn from(r: &config::YoutubeRules) -> Self {
+        Self {
+            blocked_channels: r.blocked_channels.clone(),
+            allowed_channels: r.allowed_channels.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsView {
+    pub default_mode: Mode,
+    pub block_all_channels: bool,
+    pub session_duration_minutes: u32,
+    pub sink_addresses: Vec<IpAddr>,
+    pub tamper_policy: TamperPolicyView,
+    pub calendar_import: CalendarImportView,
+    pub pause_policy: PausePolicyView,
+    pub early_end: EarlyEnd,
+    pub daily_session_limit: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PausePolicyView {
+    pub max_pauses: Option<u32>,
+    pub max_pause_minutes: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CalendarImportView {
+    pub strict_pattern: String,
+    pub precision_pattern: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TamperPolicyView {
+    pub extend_minutes: u32,
+    pub lock_after: Option<u32>,
+}
+
+impl From<&config::GlobalSettings> for SettingsView {
+    fn from(s: &config::GlobalSettings) -> Self {
+        Self {
+            default_mode: s.default_mode,
+            block_all_channels: s.block_all_channels,
+            session_duration_minutes: s.session_duration_minutes,
+            sink_addresses: s.sink_addresses.clone(),
+            tamper_policy: TamperPolicyView {
+                extend_minutes: s.tamper_policy.extend_minutes,
+                lock_after: s.tamper_policy.lock_after,
+            },
+            calendar_import: CalendarImportView {
+                strict_pattern: s.calendar_import.strict_pattern.clone(),
+                precision_pattern: s.calendar_import.precision_pattern.clone(),
+            },
+            pause_policy: PausePolicyView {
+                max_pauses: s.pause_policy.max_pauses,
+                max_pause_minutes: s.pause_policy.max_pause_minutes,
+            },
+            early_end: s.early_end.clone(),
+            daily_session_limit: s.daily_session_limit,
+        }
+    }
+}
+
+// =========================================================================
+// Errors
+// =========================================================================
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorReply {
+    pub code: ErrorCode,
+    pub message: String,
+    /// Set when the same request can succeed later without changes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+}
+
+impl ErrorReply {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            retry_after_ms: None,
+        }
+    }
+
+    pub fn retry_after(mut self, ms: u64) -> Self {
+        self.retry_after_ms = Some(ms);
+        self
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// Malformed JSON shape, unknown type, unknown or mistyped field.
+    InvalidMessage,
+    InvalidPassword,
+    PinRequired,
+    InvalidPin,
+    InvalidMode,
+    InvalidSettings,
+    InvalidSchedule,
+    NoActiveSession,
+    /// A session is already running; it has to be ended first.
+    SessionActive,
+    AlreadyPaused,
+    NotPaused,
+    PauseLimit,
+    InvalidCycle,
+    /// Daily session limit used up; `retryAfterMs` runs to local midnight.
+    LimitReached,
+    /// Early end was requested; send it again once `retryAfterMs` is up.
+    WaitRequired,
+    PhraseRequired,
+    InvalidPhrase,
+    PasswordRequired,
+    /// Password checks are throttled; see `retryAfterMs`.
+    TooManyAttempts,
+    /// The operation needs an admin password to be set first.
+    PasswordNotSet,
+    WeakPassword,
+    PinNotFound,
+    InvalidRecoveryCode,
+    Unsupported,
+    #[cfg_attr(not(windows), allow(dead_code))]
+    Registry,
+
+    // ---- CLI only: bad arguments and `AppError` variants ----
+    InvalidArguments,
+    IoError,
+    JsonError,
+    ConfigError,
+    PasswordError,
+    HostsError,
+    MessagingError,
+    CalendarError,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(msg: serde_json::Value) -> Result<Request, ErrorReply> {
+        Request::from_value(msg)
+    }
+
+    #[test]
+    fn fieldless_requests_parse() {
+        assert!(matches!(
+            parse(json!({"type": "PING"})),
+            Ok(Request::Ping {})
+        ));
+        assert!(matches!(
+            parse(json!({"type": "GET_STATE"})),
+            Ok(Request::GetState {})
+        ));
+        assert!(matches!(
+            parse(json!({"type": "RESUME_SESSION"})),
+            Ok(Request::ResumeSession {})
+        ));
+    }
+
+    #[test]
+    fn unknown_type_is_rejected() {
+        let err = parse(json!({"type": "SELF_DESTRUCT"})).unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidMessage);
+
+        let err = parse(json!({"duration": 5})).unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidMessage);
+        let err = parse(json!("PING")).unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidMessage);
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        for msg in [
+            json!({"type": "PING", "foo": 1}),
+            json!({"type": "GET_STATE", "x": 1}),
+            json!({"type": "RESUME_SESSION", "parentPin": "1234"}),
+            json!({"type": "START_SESSION", "duration": 30}),
+            json!({"type": "SYNC_SETTINGS", "settings": {"strictMode": true}}),
+        ] {
+            let err = parse(msg.clone()).unwrap_err();
+            assert_eq!(err.code, ErrorCode::InvalidMessage, "{msg}");
+        }
+    }
+
+    #[test]
+    fn mistyped_fields_are_rejected() {
+        for msg in [
+            json!({"type": "START_SESSION", "durationMinutes": "30"}),
+            json!({"type": "START_SESSION", "locked": "yes"}),
+            json!({"type": "START_SESSION", "mode": "turbo"}),
+            json!({"type": "SWITCH_MODE"}),
+            json!({"type": "EXTEND_SESSION", "minutes": -5}),
+            json!({"type": "SYNC_SETTINGS", "settings": {"sinkAddresses": ["localhost"]}}),
+        ] {
+            let err = parse(msg.clone()).unwrap_err();
+            assert_eq!(err.code, ErrorCode::InvalidMessage, "{msg}");
+        }
+    }
+
+    #[test]
+    fn camel_case_fields_are_read() {
+        let request = parse(json!({
+            "type": "START_SESSION",
+            "durationMinutes": 45,
+            "mode": "strict",
+            "locked": true,
+        }))
+        .unwrap();
+        let Request::StartSession {
+            duration_minutes,
+            mode,
+            locked,
+            ..
+        } = request
+        else {
+            panic!("wrong variant: {request:?}");
+        };
+        assert_eq!(duration_minutes, Some(45));
+        assert_eq!(mode, Some(Mode::Strict));
+        assert!(locked);
+    }
+
+    #[test]
+    fn response_shapes() {
+        let to_json = |r: &Response| serde_json::to_value(r).unwrap();
+
+        assert_eq!(to_json(&Response::ack()), json!({"status": "OK"}));
+        assert_eq!(
+            to_json(&Response::Ok(Reply::Ended { natural: true })),
+            json!({"status": "OK", "natural": true})
+        );
+        assert_eq!(
+            to_json(&Response::error(ErrorCode::PinRequired, "PIN required.")),
+            json!({"status": "ERROR", "code": "PIN_REQUIRED", "message": "PIN required."})
+        );
+        assert_eq!(
+            to_json(&Response::Error(
+                ErrorReply::new(ErrorCode::TooManyAttempts, "Wait.").retry_after(1500)
+            )),
+            json!({
+                "status": "ERROR",
+                "code": "TOO_MANY_ATTEMPTS",
+                "message": "Wait.",
+                "retryAfterMs": 1500,
+            })
+        );
+    }
+}
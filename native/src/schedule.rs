@@ -0,0 +1,439 @@
+//! Recurring focus-session schedules.
+//!
+//! A schedule is a weekly window in local wall-clock time (e.g. Mon–Fri
+//! 09:00–12:00). `restore` calls `due` on every tick and starts a session
+//! with `scheduled_id` set when a window opens; the session's `end_time` is
+//! the window's end, so the normal expiry check ends it.
+//!
+//! Windows are resolved in the system timezone on the day they open, so a
+//! DST change moves the instant, never the wall-clock time. A start or end
+//! that falls in a DST gap moves to the first minute after the gap; one
+//! that occurs twice resolves to its first occurrence. A window whose end
+//! is not after its start runs past midnight into the next day.
+//!
+//! Optional date bounds, a week interval and excluded dates let imported
+//! calendar events (see `calendar`) be represented exactly.
+
+use crate::config::Mode;
+use chrono::{DateTime, Datelike, Local, LocalResult, NaiveDate, TimeDelta, TimeZone, Weekday};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Schedule {
+    pub id: String,
+    /// Days the window opens on.
+    pub days: Vec<Day>,
+    pub start_time: ClockTime,
+    pub end_time: ClockTime,
+    pub mode: Mode,
+    #[serde(default)]
+    pub locked: bool,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// First and last dates (inclusive) a window may open on.
+    #[serde(default)]
+    pub starts_on: Option<NaiveDate>,
+    #[serde(default)]
+    pub ends_on: Option<NaiveDate>,
+    /// Open only every Nth week, counted from the week of `starts_on`.
+    #[serde(default = "default_interval")]
+    pub interval_weeks: u32,
+    /// Dates on which the window does not open.
+    #[serde(default)]
+    pub except: Vec<NaiveDate>,
+    /// Calendar file the schedule was imported from; importing that file
+    /// again replaces every schedule that came from it.
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Start (epoch ms) of the last window a session was started for, so a
+    /// session ended early isn't restarted within the same window.
+    #[serde(default)]
+    pub last_run: Option<u64>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_interval() -> u32 {
+    1
+}
+
+/// One occurrence of a schedule's window, as epoch ms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Window {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Schedule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.id.is_empty() {
+            return Err("Schedule id cannot be empty".into());
+        }
+        if self.days.is_empty() {
+            return Err(format!("Schedule '{}' has no days", self.id));
+        }
+        if self.start_time == self.end_time {
+            return Err(format!(
+                "Schedule '{}' starts and ends at {}",
+                self.id, self.start_time
+            ));
+        }
+        if !self.mode.is_active() {
+            return Err(format!("Schedule '{}' cannot use mode 'off'", self.id));
+        }
+        if self.interval_weeks == 0 {
+            return Err(format!("Schedule '{}' has a zero week interval", self.id));
+        }
+        if self.interval_weeks > 1 && self.starts_on.is_none() {
+            return Err(format!(
+                "Schedule '{}' needs a start date for its week interval",
+                self.id
+            ));
+        }
+        if let (Some(from), Some(until)) = (self.starts_on, self.ends_on) {
+            if until < from {
+                return Err(format!("Schedule '{}' ends before it starts", self.id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a window opens on `date`, regardless of time of day.
+    pub fn opens_on(&self, date: NaiveDate) -> bool {
+        if !self.days.iter().any(|d| d.weekday() == date.weekday())
+            || self.starts_on.is_some_and(|from| date < from)
+            || self.ends_on.is_some_and(|until| date > until)
+            || self.except.contains(&date)
+        {
+            return false;
+        }
+        match self.starts_on {
+            Some(from) if self.interval_weeks > 1 => {
+                let weeks = (week_start(date) - week_start(from)).num_weeks();
+                weeks % self.interval_weeks as i64 == 0
+            }
+            _ => true,
+        }
+    }
+
+    /// The occurrence of this schedule's window that contains `now`, if any.
+    fn window_at<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Option<Window> {
+        let tz = now.timezone();
+        let today = now.date_naive();
+
+        // Yesterday's window may still be open if it runs past midnight.
+        for date in [today, today.pred_opt()?] {
+            if !self.opens_on(date) {
+                continue;
+            }
+            let end_date = if self.end_time > self.start_time {
+                date
+            } else {
+                date.succ_opt()?
+            };
+            let start = resolve(&tz, date, self.start_time)?;
+            let end = resolve(&tz, end_date, self.end_time)?;
+            if start <= *now && *now < end {
+                return Some(Window {
+                    start: start.timestamp_millis() as u64,
+                    end: end.timestamp_millis() as u64,
+                });
+            }
+        }
+        None
+    }
+}
+
+/// The first enabled schedule whose window contains `now_ms` and hasn't had
+/// a session started for it yet, evaluated in the system timezone.
+pub fn due(schedules: &[Schedule], now_ms: u64) -> Option<(&Schedule, Window)> {
+    let now = Local.timestamp_millis_opt(now_ms as i64).single()?;
+    due_at(schedules, &now)
+}
+
+fn due_at<'a, Tz: TimeZone>(
+    schedules: &'a [Schedule],
+    now: &DateTime<Tz>,
+) -> Option<(&'a Schedule, Window)> {
+    schedules.iter().filter(|s| s.enabled).find_map(|s| {
+        let window = s.window_at(now)?;
+        (s.last_run != Some(window.start)).then_some((s, window))
+    })
+}
+
+/// Monday of the week containing `date`.
+pub fn week_start(date: NaiveDate) -> NaiveDate {
+    date - TimeDelta::days(date.weekday().num_days_from_monday() as i64)
+}
+
+/// Map a wall-clock time on `date` to an instant, stepping over DST gaps.
+fn resolve<Tz: TimeZone>(tz: &Tz, date: NaiveDate, time: ClockTime) -> Option<DateTime<Tz>> {
+    let mut naive = date.and_hms_opt(time.hour, time.minute, 0)?;
+    // Gaps are at most a few hours; bail out rather than loop forever on
+    // a broken zone.
+    for _ in 0..24 * 60 {
+        match tz.from_local_datetime(&naive) {
+            LocalResult::Single(t) => return Some(t),
+            LocalResult::Ambiguous(first, _) => return Some(first),
+            LocalResult::None => naive += TimeDelta::minutes(1),
+        }
+    }
+    None
+}
+
+// =========================================================================
+// Day / ClockTime
+// =========================================================================
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Day {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Day {
+    pub const ALL: [Day; 7] = [
+        Day::Mon,
+        Day::Tue,
+        Day::Wed,
+        Day::Thu,
+        Day::Fri,
+        Day::Sat,
+        Day::Sun,
+    ];
+
+    pub fn weekday(self) -> Weekday {
+        match self {
+            Day::Mon => Weekday::Mon,
+            Day::Tue => Weekday::Tue,
+            Day::Wed => Weekday::Wed,
+            Day::Thu => Weekday::Thu,
+            Day::Fri => Weekday::Fri,
+            Day::Sat => Weekday::Sat,
+            Day::Sun => Weekday::Sun,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Day::Mon => "mon",
+            Day::Tue => "tue",
+            Day::Wed => "wed",
+            Day::Thu => "thu",
+            Day::Fri => "fri",
+            Day::Sat => "sat",
+            Day::Sun => "sun",
+        }
+    }
+}
+
+impl From<Weekday> for Day {
+    fn from(w: Weekday) -> Self {
+        match w {
+            Weekday::Mon => Day::Mon,
+            Weekday::Tue => Day::Tue,
+            Weekday::Wed => Day::Wed,
+            Weekday::Thu => Day::Thu,
+            Weekday::Fri => Day::Fri,
+            Weekday::Sat => Day::Sat,
+            Weekday::Sun => Day::Sun,
+        }
+    }
+}
+
+impl FromStr for Day {
+    type Err = String;
+
+    /// Accepts "mon", "Monday", etc.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<Weekday>()
+            .map(Day::from)
+            .map_err(|_| format!("unknown day '{s}'"))
+    }
+}
+
+impl fmt::Display for Day {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Wall-clock time of day, serialized as "HH:MM".
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
+pub struct ClockTime {
+    hour: u32,
+    minute: u32,
+}
+
+impl ClockTime {
+    pub fn new(hour: u32, minute: u32) -> Option<Self> {
+        (hour < 24 && minute < 60).then_some(Self { hour, minute })
+    }
+}
+
+impl FromStr for ClockTime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid time '{s}' (expected HH:MM)");
+        let (h, m) = s.split_once(':').ok_or_else(invalid)?;
+        let hour = h.parse().map_err(|_| invalid())?;
+        let minute = m.parse().map_err(|_| invalid())?;
+        ClockTime::new(hour, minute).ok_or_else(invalid)
+    }
+}
+
+impl TryFrom<String> for ClockTime {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ClockTime> for String {
+    fn from(t: ClockTime) -> Self {
+        t.to_string()
+    }
+}
+
+impl fmt::Display for ClockTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::America::New_York;
+
+    fn schedule(days: &[Day], start: &str, end: &str) -> Schedule {
+        Schedule {
+            id: "s1".into(),
+            days: days.to_vec(),
+            start_time: start.parse().unwrap(),
+            end_time: end.parse().unwrap(),
+            mode: Mode::Strict,
+            locked: false,
+            enabled: true,
+            starts_on: None,
+            ends_on: None,
+            interval_weeks: 1,
+            except: Vec::new(),
+            source: None,
+            last_run: None,
+        }
+    }
+
+    fn ny(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<chrono_tz::Tz> {
+        New_York
+            .with_ymd_and_hms(y, mo, d, h, mi, 0)
+            .earliest()
+            .unwrap()
+    }
+
+    fn utc_ms(s: &str) -> u64 {
+        DateTime::parse_from_rfc3339(s).unwrap().timestamp_millis() as u64
+    }
+
+    #[test]
+    fn window_follows_wall_clock_across_dst() {
+        let s = schedule(&[Day::Mon], "09:00", "12:00");
+        // 2026-01-05 and 2026-07-06 are Mondays: EST (-5) vs EDT (-4).
+        let winter = s.window_at(&ny(2026, 1, 5, 10, 0)).unwrap();
+        let summer = s.window_at(&ny(2026, 7, 6, 10, 0)).unwrap();
+        assert_eq!(winter.start, utc_ms("2026-01-05T14:00:00Z"));
+        assert_eq!(summer.start, utc_ms("2026-07-06T13:00:00Z"));
+        assert!(s.window_at(&ny(2026, 1, 5, 12, 0)).is_none());
+        assert!(s.window_at(&ny(2026, 1, 6, 10, 0)).is_none());
+    }
+
+    #[test]
+    fn overnight_window_belongs_to_start_day() {
+        let s = schedule(&[Day::Fri], "22:00", "02:00");
+        // 2026-01-09 is a Friday.
+        let w = s.window_at(&ny(2026, 1, 10, 1, 0)).unwrap();
+        assert_eq!(w.start, utc_ms("2026-01-10T03:00:00Z"));
+        assert_eq!(w.end, utc_ms("2026-01-10T07:00:00Z"));
+        // Saturday night is not a window.
+        assert!(s.window_at(&ny(2026, 1, 10, 23, 0)).is_none());
+    }
+
+    #[test]
+    fn start_in_spring_forward_gap_moves_past_it() {
+        // 2026-03-08 (Sunday): clocks jump 02:00 → 03:00.
+        let s = schedule(&[Day::Sun], "02:30", "04:00");
+        let w = s.window_at(&ny(2026, 3, 8, 3, 30)).unwrap();
+        assert_eq!(w.start, utc_ms("2026-03-08T07:00:00Z"));
+        assert_eq!(w.end, utc_ms("2026-03-08T08:00:00Z"));
+    }
+
+    #[test]
+    fn repeated_hour_uses_first_occurrence() {
+        // 2026-11-01 (Sunday): 01:00–02:00 happens twice.
+        let s = schedule(&[Day::Sun], "01:30", "03:00");
+        let w = s.window_at(&ny(2026, 11, 1, 2, 30)).unwrap();
+        assert_eq!(w.start, utc_ms("2026-11-01T05:30:00Z"));
+        assert_eq!(w.end, utc_ms("2026-11-01T08:00:00Z"));
+    }
+
+    #[test]
+    fn started_or_disabled_schedules_are_not_due() {
+        let mut s = schedule(&[Day::Mon], "09:00", "12:00");
+        let now = ny(2026, 1, 5, 10, 0);
+        let window = due_at(std::slice::from_ref(&s), &now).unwrap().1;
+
+        s.last_run = Some(window.start);
+        assert!(due_at(std::slice::from_ref(&s), &now).is_none());
+
+        s.last_run = None;
+        s.enabled = false;
+        assert!(due_at(std::slice::from_ref(&s), &now).is_none());
+    }
+
+    #[test]
+    fn bounds_interval_and_exceptions() {
+        let date = |d| NaiveDate::from_ymd_opt(2026, 1, d).unwrap();
+        let mut s = schedule(&[Day::Mon], "09:00", "10:00");
+        s.starts_on = Some(date(5));
+        s.ends_on = Some(date(26));
+        s.interval_weeks = 2;
+        s.except = vec![date(19)];
+
+        let opens: Vec<u32> = (1..=31)
+            .map(date)
+            .filter(|d| s.opens_on(*d))
+            .map(|d| d.day())
+            .collect();
+        assert_eq!(opens, [5]);
+
+        s.except.clear();
+        s.interval_weeks = 1;
+        let opens: Vec<u32> = (1..=31)
+            .map(date)
+            .filter(|d| s.opens_on(*d))
+            .map(|d| d.day())
+            .collect();
+        assert_eq!(opens, [5, 12, 19, 26]);
+    }
+
+    #[test]
+    fn clock_time_parsing() {
+        assert_eq!("07:05".parse::<ClockTime>().unwrap().to_string(), "07:05");
+        assert!("24:00".parse::<ClockTime>().is_err());
+        assert!("9".parse::<ClockTime>().is_err());
+        assert_eq!("Monday".parse::<Day>().unwrap(), Day::Mon);
+    }
+}
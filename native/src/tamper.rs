@@ -0,0 +1,186 @@
+//! Tamper event log and escalation.
+//!
+//! Every time the watchdog finds the hosts block tampered with, the event is
+//! appended to `tamper.log` (one JSON object per line) under
+//! `platform::config_dir()`, and the configured `TamperPolicy` is applied to
+//! the running session.
+
+use crate::config::{self, Mode};
+use crate::hosts_manager::Tamper;
+use crate::platform;
+use crate::AppError;
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TamperEvent<'a> {
+    timestamp: u64,
+    session_id: Option<String>,
+    mode: Mode,
+    /// Tamper count for the session, including this event.
+    tamper_count: u32,
+    diff: &'a [Tamper],
+    /// Escalation applied in response to this event.
+    extended_minutes: u32,
+    locked: bool,
+}
+
+fn log_path() -> PathBuf {
+    platform::config_dir().join("tamper.log")
+}
+
+/// Record a tamper detection: bump the session's tamper count, apply the
+/// escalation policy, and append the event to the log.
+pub fn record(diff: &[Tamper]) -> Result<(), AppError> {
+    let mut extended_minutes = 0;
+    let mut locked = false;
+
+    let cfg = config::update(|cfg| {
+        let policy = cfg
+            .global_settings
+            .as_ref()
+            .map(|gs| gs.tamper_policy.clone())
+            .unwrap_or_default();
+
+        let Some(session) = cfg.session.as_mut().filter(|s| s.mode.is_active()) else {
+            return;
+        };
+
+        session.tamper_count += 1;
+
+        if policy.extend_minutes > 0 {
+            if let Some(ref mut end_time) = session.end_time {
+                *end_time += policy.extend_minutes as u64 * 60 * 1000;
+                extended_minutes = policy.extend_minutes;
+            }
+        }
+
+        if let Some(limit) = policy.lock_after {
+            if session.tamper_count >= limit && !session.locked {
+                session.locked = true;
+                locked = true;
+            }
+        }
+    })?;
+
+    let session = cfg.session.as_ref();
+    let event = TamperEvent {
+        timestamp: config::now_ms(),
+        session_id: session.and_then(|s| s.id.clone()),
+        mode: cfg.session_mode(),
+        tamper_count: session.map_or(0, |s| s.tamper_count),
+        diff,
+        extended_minutes,
+        locked,
+    };
+
+    fs::create_dir_all(platform::config_dir())?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path())?;
+    writeln!(file, "{}", serde_json::to_string(&event)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GlobalSettings, SessionState, TamperPolicy};
+    use crate::platform::sandbox::Sandbox;
+
+    const END: u64 = 1_700_000_000_000;
+
+    fn start(policy: TamperPolicy) {
+        config::update(|cfg| {
+            cfg.global_settings = Some(GlobalSettings {
+                tamper_policy: policy,
+                ..Default::default()
+            });
+            cfg.session = Some(SessionState {
+                mode: Mode::Strict,
+                end_time: Some(END),
+                id: Some("s1".into()),
+                ..Default::default()
+            });
+        })
+        .unwrap();
+    }
+
+    fn session() -> SessionState {
+        config::load().unwrap().session.unwrap()
+    }
+
+    fn logged() -> Vec<serde_json::Value> {
+        fs::read_to_string(log_path())
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn default_policy_only_counts() {
+        let _sandbox = Sandbox::new();
+        start(TamperPolicy::default());
+        record(&[Tamper::MissingSection]).unwrap();
+
+        let s = session();
+        assert_eq!(s.tamper_count, 1);
+        assert_eq!(s.end_time, Some(END));
+        assert!(!s.locked);
+        let log = logged();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0]["sessionId"], "s1");
+        assert_eq!(log[0]["extendedMinutes"], 0);
+    }
+
+    #[test]
+    fn each_tamper_extends_and_threshold_locks() {
+        let _sandbox = Sandbox::new();
+        start(TamperPolicy {
+            extend_minutes: 5,
+            lock_after: Some(2),
+        });
+
+        record(&[Tamper::MissingSection]).unwrap();
+        let s = session();
+        assert_eq!(s.tamper_count, 1);
+        assert_eq!(s.end_time, Some(END + 5 * 60 * 1000));
+        assert!(!s.locked, "below the threshold");
+
+        record(&[Tamper::MissingSection]).unwrap();
+        let s = session();
+        assert_eq!(s.tamper_count, 2);
+        assert_eq!(s.end_time, Some(END + 10 * 60 * 1000));
+        assert!(s.locked);
+
+        // Locking is reported once, on the event that crossed the threshold.
+        record(&[Tamper::MissingSection]).unwrap();
+        let locked: Vec<bool> = logged()
+            .iter()
+            .map(|e| e["locked"].as_bool().unwrap())
+            .collect();
+        assert_eq!(locked, [false, true, false]);
+        assert_eq!(logged()[2]["tamperCount"], 3);
+    }
+
+    #[test]
+    fn no_escalation_without_an_active_session() {
+        let _sandbox = Sandbox::new();
+        start(TamperPolicy {
+            extend_minutes: 5,
+            lock_after: Some(1),
+        });
+        config::update(|cfg| cfg.session = None).unwrap();
+
+        record(&[Tamper::MissingSection]).unwrap();
+        assert!(config::load().unwrap().session.is_none());
+        let log = logged();
+        assert_eq!(log[0]["tamperCount"], 0);
+        assert_eq!(log[0]["locked"], false);
+    }
+}
@@ -1,34 +1,266 @@
 //! Background watchdog thread.
 //!
-//! Periodically verifies that the hosts-file entries haven't been removed
-//! or tampered with. Reapplies them if anything is missing.
+//! Verifies that the hosts-file entries haven't been removed or tampered
+//! with, and reapplies them if anything is missing. Foreign lines that
+//! override a blocked host can't be repaired without editing them, so each
+//! is reported once, when it appears. The expected entries
+//! are built from the config on every check, so sessions started, paused
+//! or ended by another process (the CLI, `restore`, a second browser) are
+//! never mistaken for tampering. Paused sessions and cycle breaks enforce
+//! an empty blocklist, so the watchdog stands down until work resumes.
+//!
+//! On Linux the hosts file and its parent directory are watched with
+//! inotify, so edits — including atomic replace-by-rename — are repaired as
+//! soon as they happen. Elsewhere, or if inotify can't be set up, the file
+//! is polled every `CHECK_INTERVAL`.
 
-use crate::hosts_manager;
-use std::sync::{Arc, Mutex};
+use crate::hosts_manager::{self, Tamper};
+use crate::{collect_blocklist, config, tamper, AppError};
 use std::thread;
 use std::time::Duration;
 
 const CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
-/// Spawn a background thread that calls `ensure_hosts_integrity` on a loop.
-pub fn start(blocked_domains: Arc<Mutex<Vec<String>>>) -> thread::JoinHandle<()> {
-    thread::spawn(move || loop {
-        thread::sleep(CHECK_INTERVAL);
+/// Spawn a background thread that keeps the hosts file in line with the
+/// config.
+pub fn start() -> thread::JoinHandle<()> {
+    thread::spawn(|| {
+        #[cfg(target_os = "linux")]
+        if let Err(e) = watch_inotify() {
+            eprintln!("[Watchdog] inotify unavailable ({e}), falling back to polling.");
+        }
 
-        let domains = match blocked_domains.lock() {
-            Ok(guard) => guard.clone(),
-            Err(e) => {
-                eprintln!("[Watchdog] Lock poisoned: {e}");
-                continue;
-            }
-        };
+        poll()
+    })
+}
 
-        if domains.is_empty() {
-            continue;
-        }
+/// Polling fallback: check every `CHECK_INTERVAL`, forever.
+fn poll() {
+    let mut standing = Vec::new();
+    loop {
+        thread::sleep(CHECK_INTERVAL);
+        check(&mut standing);
+    }
+}
 
-        if let Err(e) = hosts_manager::ensure_integrity(&domains) {
+/// Run one integrity check and record anything that had to be repaired,
+/// and any override that isn't in `standing`, the overriding lines the
+/// previous check found.
+fn check(standing: &mut Vec<String>) {
+    let tampered = match verify() {
+        Ok(tampered) => tampered,
+        Err(e) => {
             eprintln!("[Watchdog] Integrity check failed: {e}");
+            return;
         }
-    })
+    };
+
+    let (overrides, repaired): (Vec<Tamper>, Vec<Tamper>) = tampered
+        .into_iter()
+        .partition(|t| matches!(t, Tamper::Override { .. }));
+    // Moving the block shifts line numbers, so compare the lines themselves.
+    let lines: Vec<String> = overrides
+        .iter()
+        .filter_map(|t| match t {
+            Tamper::Override { found, .. } => Some(found.clone()),
+            _ => None,
+        })
+        .collect();
+    let new_overrides: Vec<Tamper> = overrides
+        .into_iter()
+        .zip(&lines)
+        .filter(|(_, line)| !standing.contains(line))
+        .map(|(t, _)| t)
+        .collect();
+    *standing = lines;
+
+    if repaired.is_empty() && new_overrides.is_empty() {
+        return;
+    }
+    for t in &repaired {
+        eprintln!("[Watchdog] Hosts file tampered, restored: {t}");
+    }
+    for t in &new_overrides {
+        eprintln!("[Watchdog] Hosts file overrides a block, remove it: {t}");
+    }
+    let tampered = [repaired, new_overrides].concat();
+    if let Err(e) = tamper::record(&tampered) {
+        eprintln!("[Watchdog] Cannot record tamper event: {e}");
+    }
+}
+
+/// Compare the hosts file with the blocklist the config calls for, and
+/// repair it. The hosts lock is held throughout, so a session change that
+/// is still being written out isn't mistaken for tampering.
+fn verify() -> Result<Vec<Tamper>, AppError> {
+    let _hosts = hosts_manager::lock()?;
+    let blocklist = collect_blocklist(&config::load()?);
+    hosts_manager::ensure_integrity(&blocklist)
+}
+
+/// Event-driven watcher. Only returns on error (e.g. inotify unsupported or
+/// watch limit reached), so the caller can fall back to polling.
+#[cfg(target_os = "linux")]
+fn watch_inotify() -> std::io::Result<()> {
+    use crate::platform;
+    use inotify::{Inotify, WatchMask};
+    use std::io;
+
+    let hosts = platform::hosts_file_path();
+    let dir = hosts
+        .parent()
+        .ok_or_else(|| io::Error::other("hosts file has no parent directory"))?;
+    let name = hosts
+        .file_name()
+        .ok_or_else(|| io::Error::other("hosts file has no file name"))?;
+
+    let mut inotify = Inotify::init()?;
+
+    // The directory watch catches creates, deletes and renames over the
+    // file (editors and `sed -i` replace rather than rewrite in place).
+    inotify.watches().add(
+        dir,
+        WatchMask::CLOSE_WRITE
+            | WatchMask::MODIFY
+            | WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO,
+    )?;
+
+    let file_mask = WatchMask::MODIFY
+        | WatchMask::CLOSE_WRITE
+        | WatchMask::ATTRIB
+        | WatchMask::DELETE_SELF
+        | WatchMask::MOVE_SELF;
+
+    // Catch anything that happened before the watch was in place.
+    let mut standing = Vec::new();
+    check(&mut standing);
+
+    let mut buffer = [0u8; 4096];
+    loop {
+        // Re-arm the file watch: after a replace it points at a new inode.
+        // Failure is fine if the file is momentarily gone — the directory
+        // watch still fires when it comes back.
+        let _ = inotify.watches().add(&hosts, file_mask);
+
+        let relevant = inotify
+            .read_events_blocking(&mut buffer)?
+            .any(|event| event.name.is_none_or(|n| n == name));
+
+        if relevant {
+            check(&mut standing);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Mode;
+    use crate::handle_message;
+    use crate::platform::{self, sandbox::Sandbox};
+    use crate::protocol::Request;
+    use std::fs;
+
+    fn start_strict() {
+        handle_message(Request::StartSession {
+            duration_minutes: Some(30),
+            scheduled_id: None,
+            locked: false,
+            mode: Some(Mode::Strict),
+            cycle: None,
+        })
+        .unwrap();
+    }
+
+    fn hosts() -> String {
+        fs::read_to_string(platform::hosts_file_path()).unwrap()
+    }
+
+    fn tamper_count() -> u32 {
+        config::load()
+            .unwrap()
+            .session
+            .map_or(0, |s| s.tamper_count)
+    }
+
+    fn logged() -> bool {
+        platform::config_dir().join("tamper.log").exists()
+    }
+
+    #[test]
+    fn external_pause_is_not_tamper() {
+        let _sandbox = Sandbox::new();
+        start_strict();
+        assert!(hosts().contains("youtube.com"));
+
+        // What `focus-blocker-native pause` does from another process.
+        handle_message(Request::PauseSession { parent_pin: None }).unwrap();
+        check(&mut Vec::new());
+
+        assert!(!hosts().contains("youtube.com"));
+        assert_eq!(tamper_count(), 0);
+        assert!(!logged());
+    }
+
+    #[test]
+    fn session_ended_elsewhere_is_not_tamper() {
+        let _sandbox = Sandbox::new();
+        start_strict();
+
+        // Config changed without the hosts file (e.g. a restored backup):
+        // nothing is expected any more, so nothing is repaired.
+        config::update(|cfg| cfg.session = None).unwrap();
+        check(&mut Vec::new());
+
+        assert!(hosts().contains("youtube.com"));
+        assert!(!logged());
+    }
+
+    #[test]
+    fn removed_block_is_restored_and_recorded() {
+        let _sandbox = Sandbox::new();
+        start_strict();
+
+        fs::write(platform::hosts_file_path(), "127.0.0.1 localhost\n").unwrap();
+        check(&mut Vec::new());
+
+        assert!(hosts().contains("youtube.com"));
+        assert_eq!(tamper_count(), 1);
+        assert!(logged());
+
+        // Repaired: the next check finds nothing.
+        check(&mut Vec::new());
+        assert_eq!(tamper_count(), 1);
+    }
+
+    #[test]
+    fn standing_override_is_recorded_once() {
+        let _sandbox = Sandbox::new();
+        start_strict();
+        let mut standing = Vec::new();
+
+        let mut content = hosts();
+        content.push_str("93.184.216.34 youtube.com\n");
+        fs::write(platform::hosts_file_path(), &content).unwrap();
+        check(&mut standing);
+        assert_eq!(tamper_count(), 1);
+        assert_eq!(standing.len(), 1);
+
+        // Still there, and left alone: not counted again.
+        check(&mut standing);
+        assert_eq!(tamper_count(), 1);
+        assert_eq!(hosts(), content);
+
+        // Removed and re-added: a new event.
+        let removed = content.replace("93.184.216.34 youtube.com\n", "");
+        fs::write(platform::hosts_file_path(), removed).unwrap();
+        check(&mut standing);
+        assert!(standing.is_empty());
+        fs::write(platform::hosts_file_path(), &content).unwrap();
+        check(&mut standing);
+        assert_eq!(tamper_count(), 2);
+    }
 }